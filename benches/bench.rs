@@ -1,10 +1,59 @@
-use dependent_map::{Map, families::Singleton};
+use dependent_map::{Map, families::Singleton, variants::{PartitionedMap, TypeMap}};
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
+// Uniform lookup of a value by its type, the maps differ in what `get` returns.
+trait Lookup {
+    fn lookup<A: 'static>(&self) -> Option<&A>;
+}
+
+impl Lookup for Map<Singleton> {
+    fn lookup<A: 'static>(&self) -> Option<&A> {
+        match self.get_default::<A>() {
+            Some(entry) => Some(&entry.some),
+            None => None,
+        }
+    }
+}
+
+impl Lookup for PartitionedMap<Singleton> {
+    fn lookup<A: 'static>(&self) -> Option<&A> {
+        match self.get_default::<A>() {
+            Some(entry) => Some(&entry.some),
+            None => None,
+        }
+    }
+}
+
+impl Lookup for TypeMap {
+    fn lookup<A: 'static>(&self) -> Option<&A> {
+        self.get::<A>()
+    }
+}
+
+// Run the same benchmark once for each storage layout, to compare them side by side.
+macro_rules! bench_maps {
+    ($c:expr, $name:expr, |$b:ident, $Map:ident| $body:expr) => {{
+        let mut group = $c.benchmark_group($name);
+        {
+            type $Map = Map<Singleton>;
+            group.bench_function("Map", |$b| $body);
+        }
+        {
+            type $Map = PartitionedMap<Singleton>;
+            group.bench_function("PartitionedMap", |$b| $body);
+        }
+        {
+            type $Map = TypeMap;
+            group.bench_function("TypeMap", |$b| $body);
+        }
+        group.finish();
+    }};
+}
+
 fn insertion(b: &mut Criterion) {
-    b.bench_function("insertion", |b| b.iter(|| {
-        let mut data = black_box(Map::<Singleton>::new());
+    bench_maps!(b, "insertion", |b, M| b.iter(|| {
+        let mut data = black_box(M::new());
         for _ in 0..100 {
             let _ = data.insert(42);
         }
@@ -12,22 +61,22 @@ fn insertion(b: &mut Criterion) {
 }
 
 fn get_missing(b: &mut Criterion) {
-    b.bench_function("get_missing", |b| b.iter(|| {
-        let data = black_box(Map::<Singleton>::new());
+    bench_maps!(b, "get_missing", |b, M| b.iter(|| {
+        let data = black_box(M::new());
         for _ in 0..100 {
-            assert_eq!(data.get_default::<i32>(), None);
+            assert_eq!(data.lookup::<i32>(), None);
         }
     }));
 }
 
 fn get_present(b: &mut Criterion) {
-    b.bench_function("get_present", |b| b.iter(|| {
-        let mut data = Map::<Singleton>::new();
+    bench_maps!(b, "get_present", |b, M| b.iter(|| {
+        let mut data = M::new();
         let _ = data.insert(42);
         let data = black_box(data);
         // These inner loops are a feeble attempt to drown the other factors.
         for _ in 0..100 {
-            assert_eq!(**data.get_default::<i32>().expect(""), 42);
+            assert_eq!(*data.lookup::<i32>().expect(""), 42);
         }
     }));
 }
@@ -36,16 +85,17 @@ macro_rules! big_benchmarks {
     ($name:ident, $($T:ident)*) => (
         fn $name(b: &mut Criterion) {
             $(
+                #[allow(dead_code)]
                 struct $T(&'static str);
             )*
-            b.bench_function(stringify!($name), |b| b.iter(|| {
-                let mut data = black_box(Map::<Singleton>::new());
+            bench_maps!(b, stringify!($name), |b, M| b.iter(|| {
+                let mut data = black_box(M::new());
                 $(
                     let _ = data.insert($T(stringify!($T)));
                 )*
                 let data = black_box(data);
                 $(
-                    let _ = data.get_default::<$T>();
+                    let _ = data.lookup::<$T>();
                 )*
             }));
         }
@@ -1,7 +1,12 @@
-#[rustversion::nightly]
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(loom)");
+    enable_unstable_features();
+}
+
+#[rustversion::nightly]
+fn enable_unstable_features() {
     println!("cargo:rustc-cfg=feature=\"unstable_features\"");
 }
 
 #[rustversion::not(nightly)]
-fn main() {}
+fn enable_unstable_features() {}
@@ -1,6 +1,9 @@
-use crate::{EntryFamily, HashEntry};
-use std::ops::Deref;
-use std::ops::DerefMut;
+use crate::{EntryAt, EntryFamily, HashEntry};
+use core::cell::RefCell;
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ops::DerefMut;
 
 /// Used as first argument to [`Map`] so that to each type, exactly one value of that type is associated.
 ///
@@ -29,6 +32,14 @@ impl<A> HashEntry for Some<A> {
     fn split_mut(&mut self) -> (&(), &mut A) {
         (&(), &mut self.some)
     }
+    #[inline]
+    fn into_parts(self) -> ((), A) {
+        ((), self.some)
+    }
+    #[inline]
+    fn from_parts((): (), some: A) -> Self {
+        Some { some }
+    }
 }
 
 impl<A> From<A> for Some<A> {
@@ -50,3 +61,122 @@ impl<A> DerefMut for Some<A> {
         &mut self.some
     }
 }
+
+// Transparent, just like the `repr`
+#[cfg(feature = "serde")]
+impl<A: serde::Serialize> serde::Serialize for Some<A> {
+    #[inline]
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.some.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, A: serde::Deserialize<'de>> serde::Deserialize<'de> for Some<A> {
+    #[inline]
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Some {
+            some: A::deserialize(deserializer)?,
+        })
+    }
+}
+
+/// Wraps another entry family `E`, so that each value is stored in a [`RefCell`].
+/// Used as the entry family of the map inside [`CellMap`].
+///
+/// [`CellMap`]: crate::variants::CellMap
+pub struct Celled<E: ?Sized>(PhantomData<E>);
+impl<A: ?Sized, E: ?Sized + EntryFamily<A>> EntryFamily<A> for Celled<E> {
+    type Result = CellEntry<EntryAt<E, A>>;
+}
+
+/// Entry for [`Celled`], storing the key of the wrapped entry and its value in a [`RefCell`].
+pub struct CellEntry<T: HashEntry> {
+    key: T::Key,
+    value: RefCell<T::Value>,
+}
+
+impl<T: HashEntry> Debug for CellEntry<T>
+where
+    T::Key: Debug,
+    T::Value: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CellEntry")
+            .field("key", &self.key)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+impl<T: HashEntry> Clone for CellEntry<T>
+where
+    T::Key: Clone,
+    T::Value: Clone,
+{
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    #[inline]
+    fn clone(&self) -> Self {
+        CellEntry {
+            key: self.key.clone(),
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<T: HashEntry> PartialEq for CellEntry<T>
+where
+    T::Value: PartialEq,
+{
+    /// # Panics
+    ///
+    /// Panics if the value in either entry is currently mutably borrowed.
+    #[inline]
+    fn eq(&self, rhs: &Self) -> bool {
+        self.key == rhs.key && self.value == rhs.value
+    }
+}
+
+impl<T: HashEntry> Eq for CellEntry<T> where T::Value: Eq {}
+
+impl<T: HashEntry> HashEntry for CellEntry<T> {
+    type Key = T::Key;
+    type Value = RefCell<T::Value>;
+    #[inline]
+    fn split_ref(&self) -> (&T::Key, &RefCell<T::Value>) {
+        (&self.key, &self.value)
+    }
+    #[inline]
+    fn split_mut(&mut self) -> (&T::Key, &mut RefCell<T::Value>) {
+        (&self.key, &mut self.value)
+    }
+    #[inline]
+    fn into_parts(self) -> (T::Key, RefCell<T::Value>) {
+        (self.key, self.value)
+    }
+    #[inline]
+    fn from_parts(key: T::Key, value: RefCell<T::Value>) -> Self {
+        CellEntry { key, value }
+    }
+}
+
+impl<T: HashEntry> CellEntry<T> {
+    #[inline]
+    /// Unwrap the cell, returning the wrapped entry.
+    pub fn into_inner(self) -> T {
+        T::from_parts(self.key, self.value.into_inner())
+    }
+}
+
+impl<T: HashEntry> From<T> for CellEntry<T> {
+    #[inline]
+    fn from(entry: T) -> Self {
+        let (key, value) = entry.into_parts();
+        CellEntry {
+            key,
+            value: RefCell::new(value),
+        }
+    }
+}
@@ -1,9 +1,30 @@
 //! This crate provides the [`Map`] type, a safe and convenient store for one or multiple values for each type.
 //! 
+//! # `no_std` support
+//! 
+//! Disabling the default `std` feature makes the crate `#![no_std]`, only depending on `alloc`.
+//! Without `std`, `variants::ConcurrentMap` is not available, and the error types don't implement
+//! `std::error::Error`.
+//! 
+//! The seeding of the [`DefaultHashBuilder`] does not depend on the `std` feature. On targets
+//! with a known OS it is seeded from the OS random number generator, elsewhere from fixed seeds
+//! mixed with a counter and stack addresses. On such targets, pass a randomly seeded hash builder
+//! with [`Map::with_hasher`] if keys can be chosen by an attacker.
+//! 
+//! # Optional features
+//! 
+//! - `bumpalo`: the arena-backed `variants::ArenaMap`.
+//! - `serde`: (de)serialize maps through a `registry::TypeRegistry`.
+//! - `snapshot`: versioned binary snapshots of maps, see `registry::snapshot`. Implies `serde`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![cfg_attr(feature = "unstable_features", feature(unsize, coerce_unsized))]
 #![warn(missing_docs, unused_results)]
+#![allow(clippy::multiple_bound_locations)]
+
+extern crate alloc;
 
 mod map;
+mod storage;
 pub use map::*;
 #[cfg(test)]
 mod tests;
@@ -11,3 +32,11 @@ mod tests;
 pub mod families;
 /// Variants of [`Map`] with a specific internal storage.
 pub mod variants;
+#[cfg(feature = "serde")]
+pub mod registry;
+
+// Used by exported macros, to not require `extern crate alloc` in `no_std` crates using them
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::boxed::Box;
+}
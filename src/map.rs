@@ -1,27 +1,31 @@
 use core::hash::BuildHasher;
-use hashbrown::raw::{Bucket, RawTable};
-use std::any::Any;
-use std::any::TypeId;
-use std::borrow::Borrow;
-use std::fmt::Debug;
-use std::fmt::Formatter;
-use std::hash::Hash;
-use std::hash::Hasher;
-use std::marker::PhantomData;
-use std::ops::Deref;
-use std::ops::DerefMut;
+use hashbrown::raw::{Bucket, RawDrain, RawIntoIter, RawIter, RawTable};
+use core::any::Any;
+use core::any::TypeId;
+use core::borrow::Borrow;
+use core::fmt::Debug;
+use core::fmt::Formatter;
+use core::hash::Hash;
+use core::hash::Hasher;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ops::DerefMut;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::storage::Storage;
 
 pub use dyn_clone::DynClone;
 
 #[inline(always)]
-fn unreachable_internal_invariant(_reason: &'static str) -> ! {
+pub(crate) fn unreachable_internal_invariant(_reason: &'static str) -> ! {
     #[cfg(debug_assertions)]
     {
-        unreachable!(_reason)
+        unreachable!("{}", _reason)
     }
     #[cfg(not(debug_assertions))]
     unsafe {
-        std::hint::unreachable_unchecked()
+        core::hint::unreachable_unchecked()
     }
 }
 
@@ -67,16 +71,26 @@ impl<T: Sized + Any + Hash, H: Hasher> HashableAny<H> for T {
 /// 
 /// Refer to [`create_entry_impl`] for the prefered method of writing implementations of this.
 /// 
-/// # Unsafety
+/// # Safety
 /// 
-/// Implementations promise that the returned Box contains the passed in entry in a fat pointer.
+/// Implementations promise that the returned Box contains the passed in entry in a fat pointer,
+/// and that [`CreateEntry::coerce_entry`], if it returns a pointer, only changes its type, not its
+/// address.
 /// This must only be implemented for trait objects implying [`RefAny`], and when downcasting
-/// the returned Box, the original entry must be returned.
+/// the returned Box or the coerced pointer, the original entry must be returned.
 /// 
 /// [`create_entry_impl`]: crate::create_entry_impl
 pub unsafe trait CreateEntry<A: ?Sized, E: ?Sized + EntryFamily<A>> {
     /// Create a boxed up internal storage from an entry
     fn from_entry(e: EntryAt<E, A>) -> Box<Self>;
+    /// Coerce a pointer to an entry into a pointer to the internal storage, used for entries
+    /// that are small enough to be stored inline instead of boxed up.
+    ///
+    /// Returns `None` by default, in which case all entries are boxed up.
+    #[inline]
+    fn coerce_entry(_ptr: *mut InnerEntry<E, A>) -> Option<*mut Self> {
+        None
+    }
 }
 
 /// Trait of entries that be stored in a hashmap-based type-dependent map.
@@ -89,11 +103,23 @@ pub trait HashEntry {
     fn split_ref(&self) -> (&Self::Key, &Self::Value);
     /// Split the mutable entry into key + mutable value
     fn split_mut(&mut self) -> (&Self::Key, &mut Self::Value);
+    /// Split the owned entry into key + value
+    fn into_parts(self) -> (Self::Key, Self::Value)
+    where
+        Self: Sized;
+    /// Assemble an entry from key + value, the inverse of [`HashEntry::into_parts`]
+    fn from_parts(key: Self::Key, value: Self::Value) -> Self
+    where
+        Self: Sized;
 }
 
 /// Object-safe [`PartialEq`] for comparing trait objects
 pub trait DynPartialEq {
     /// Unsafe comparison: `other` is assumed to have same TypeId as Self.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `other` has the same [`TypeId`] as `Self`.
     unsafe fn eq_dyn_unsafe(&self, other: &dyn Any) -> bool;
     /// Compare equality against a trait object implementing Any.
     fn eq_dyn(&self, other: &dyn Any) -> bool;
@@ -129,22 +155,22 @@ impl<T: 'static + Eq> DynEq for T {}
 /// [`Debug`] for entries
 pub trait DebugEntry {
     /// Format the key of the entry
-    fn fmt_key(&self, _: &mut Formatter<'_>) -> std::fmt::Result;
+    fn fmt_key(&self, _: &mut Formatter<'_>) -> core::fmt::Result;
     /// Format the value of the entry
-    fn fmt_value(&self, _: &mut Formatter<'_>) -> std::fmt::Result;
+    fn fmt_value(&self, _: &mut Formatter<'_>) -> core::fmt::Result;
 }
 impl<A: 'static + ?Sized, E: ?Sized + EntryFamily<A>> DebugEntry for InnerEntry<E, A>
 where
     KeyAt<E, A>: Debug,
     ValueAt<E, A>: Debug,
 {
-    fn fmt_key(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt_key(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("")
-            .field("type", &std::any::type_name::<A>())
+            .field("type", &core::any::type_name::<A>())
             .field("key", self.key())
             .finish()
     }
-    fn fmt_value(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt_value(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         self.value().fmt(f)
     }
 }
@@ -163,13 +189,14 @@ pub type KeyAt<E, A> = <EntryAt<E, A> as HashEntry>::Key;
 /// Convenience type alias for the value part of the [`HashEntry`] of an [`EntryFamily`] at a specific argument.
 pub type ValueAt<E, A> = <EntryAt<E, A> as HashEntry>::Value;
 
-// FIXME: using Box here correct? I have no idea what the correct type to contain in PhantomData is, actually.
-type NonOwningPhantomPointer<A> = PhantomData<Box<A>>;
+// The argument type is only a type-level tag and never stored, so the marker must neither own an
+// `A` nor make `Send`/`Sync` of an entry depend on it. A function pointer type achieves both.
+pub(crate) type NonOwningPhantomPointer<A> = PhantomData<fn() -> Box<A>>;
 
 #[repr(transparent)]
 #[allow(missing_docs)]
 pub struct InnerEntry<E: ?Sized + EntryFamily<A>, A: ?Sized> {
-    entry: EntryAt<E, A>,
+    pub(crate) entry: EntryAt<E, A>,
     _marker: NonOwningPhantomPointer<A>,
 }
 
@@ -199,15 +226,15 @@ impl<A: ?Sized, E: ?Sized + EntryFamily<A>> InnerEntry<E, A> {
     }
 
     #[inline]
-    fn key(&self) -> &KeyAt<E, A> {
+    pub(crate) fn key(&self) -> &KeyAt<E, A> {
         self.split_ref().0
     }
     #[inline]
-    fn value(&self) -> &ValueAt<E, A> {
+    pub(crate) fn value(&self) -> &ValueAt<E, A> {
         self.split_ref().1
     }
     #[inline]
-    fn value_mut(&mut self) -> &mut ValueAt<E, A> {
+    pub(crate) fn value_mut(&mut self) -> &mut ValueAt<E, A> {
         self.split_mut().1
     }
 }
@@ -239,18 +266,40 @@ where
     fn clone(&self) -> Self {
         Self {
             entry: self.entry.clone(),
-            _marker: std::marker::PhantomData,
+            _marker: core::marker::PhantomData,
+        }
+    }
+}
+
+// Describes the argument `A` an entry was created for. Stored as function pointers, since
+// `TypeId::of` and `type_name` can not be evaluated in a const context on stable.
+#[derive(Clone, Copy)]
+struct ArgumentType {
+    type_id: fn() -> TypeId,
+    type_name: fn() -> &'static str,
+}
+
+impl ArgumentType {
+    #[inline]
+    fn of<A: 'static + ?Sized>() -> Self {
+        Self {
+            type_id: TypeId::of::<A>,
+            type_name: core::any::type_name::<A>,
         }
     }
 }
 
-struct RawEntry<E: ?Sized, I: ?Sized> {
+/// A type-erased entry of a [`Map`], as returned when iterating over all entries.
+///
+/// Each entry remembers the argument type `A` it was created for, so that it can be
+/// inspected without knowing that type statically, and converted back if the type is known.
+pub struct ErasedEntry<E: ?Sized, I: ?Sized, const N: usize = DEFAULT_INLINE_WORDS> {
     // Not actually *Any*, but a concrete instantiation of InnerEntry<_, E>
-    // Implementation note: prefer to use `.inner()` or `.inner_mut()` when
-    // using the Any interface, otherwise you might unintentionally work on the
-    // Box instead of its content.
-    inner: Box<I>,
-    hash: u64,
+    // Small entries are stored inline, larger ones are boxed, see `Storage`.
+    // Implementation note: use `.inner()` or `.inner_mut()` to access the entry.
+    pub(crate) inner: Storage<I, N>,
+    pub(crate) hash: u64,
+    argument: ArgumentType,
     _marker: NonOwningPhantomPointer<E>,
 }
 
@@ -258,7 +307,7 @@ struct RawEntry<E: ?Sized, I: ?Sized> {
 unsafe impl<A: 'static + ?Sized, E: 'static + ?Sized + EntryFamily<A>, I: ?Sized> CreateEntry<A, E>
     for I
 where
-    InnerEntry<E, A>: std::marker::Unsize<I>,
+    InnerEntry<E, A>: core::marker::Unsize<I>,
 {
     #[inline]
     fn from_entry(entry: EntryAt<E, A>) -> Box<Self>
@@ -267,10 +316,14 @@ where
     {
         let boxed: Box<InnerEntry<E, A>> = Box::new(InnerEntry {
             entry,
-            _marker: std::marker::PhantomData,
+            _marker: core::marker::PhantomData,
         });
         boxed // Unsize coercion
     }
+    #[inline]
+    fn coerce_entry(ptr: *mut InnerEntry<E, A>) -> Option<*mut Self> {
+        Some(ptr) // Unsize coercion
+    }
 }
 
 mod macro_hygiene {
@@ -287,7 +340,7 @@ mod macro_hygiene {
                 /// ```rust
                 /// # #[macro_use] extern crate dependent_map;
                 /// # use dependent_map::{HashableAny, DynClone, DynEq, EntryAt};
-                /// # use std::hash::Hasher;
+                /// # use core::hash::Hasher;
                 /// // Some trait alias we want to use as `Map<E, S, dyn SomeTraitFoo<H>>`
                 /// // In this case, the Map should be cloneable and comparable for equality.
                 /// trait SomeTraitFoo<H: Hasher>: HashableAny<H> + DynClone + DynEq {}
@@ -314,14 +367,20 @@ mod macro_hygiene {
     #[cfg(not(feature = "unstable_features"))]
     macro_rules! create_entry_impl {
         ($hashable_name:path $(where $($bounded_type:ty: $bound:tt$( + $other_bounds:tt)*,)*)?) => {
-            unsafe impl<H: ::std::hash::Hasher, A: 'static + ?Sized, E: 'static + ?Sized + $crate::EntryFamily<A>>
+            unsafe impl<H: ::core::hash::Hasher, A: 'static + ?Sized, E: 'static + ?Sized + $crate::EntryFamily<A>>
                 $crate::CreateEntry<A, E> for dyn $hashable_name
             where $($($bounded_type: $bound $(+ $other_bounds)*,)*)?
             {
                 #[inline]
-                fn from_entry(entry: $crate::EntryAt<E, A>) -> ::std::boxed::Box<Self> {
+                fn from_entry(entry: $crate::EntryAt<E, A>) -> $crate::__private::Box<Self> {
                     let inner_entry: $crate::InnerEntry<E, A> = $crate::InnerEntry::new(entry);
-                    ::std::boxed::Box::new(inner_entry)
+                    $crate::__private::Box::new(inner_entry)
+                }
+                #[inline]
+                fn coerce_entry(
+                    ptr: *mut $crate::InnerEntry<E, A>,
+                ) -> ::core::option::Option<*mut Self> {
+                    ::core::option::Option::Some(ptr)
                 }
             }
         };
@@ -335,34 +394,98 @@ mod macro_hygiene {
     }
 }
 
-impl<E: ?Sized, I: ?Sized> RawEntry<E, I> {
+impl<E: ?Sized, I: ?Sized, const N: usize> ErasedEntry<E, I, N> {
+    #[inline]
+    pub(crate) fn inner(&self) -> &I {
+        self.inner.get()
+    }
+    #[inline]
+    pub(crate) fn inner_mut(&mut self) -> &mut I {
+        self.inner.get_mut()
+    }
+    #[inline]
+    /// Get the inner entry, without checking the argument type.
+    ///
+    /// # Safety
+    ///
+    /// The entry must have been stored for the argument type `A`.
+    pub(crate) unsafe fn downcast_ref_unchecked<A: ?Sized>(&self) -> &InnerEntry<E, A>
+    where
+        E: EntryFamily<A>,
+    {
+        self.inner.get_unchecked()
+    }
     #[inline]
-    fn inner(&self) -> &I {
-        &self.inner
+    /// Get the inner entry mutably, without checking the argument type.
+    ///
+    /// # Safety
+    ///
+    /// The entry must have been stored for the argument type `A`.
+    pub(crate) unsafe fn downcast_mut_unchecked<A: ?Sized>(&mut self) -> &mut InnerEntry<E, A>
+    where
+        E: EntryFamily<A>,
+    {
+        self.inner.get_unchecked_mut()
+    }
+    #[inline]
+    /// Take the inner entry, without checking the argument type.
+    ///
+    /// # Safety
+    ///
+    /// The entry must have been stored for the argument type `A`.
+    pub(crate) unsafe fn downcast_unchecked<A: ?Sized>(self) -> InnerEntry<E, A>
+    where
+        E: EntryFamily<A>,
+    {
+        self.inner.take()
+    }
+    #[inline]
+    /// The [`TypeId`] of the argument type `A` this entry was stored for.
+    pub fn type_id(&self) -> TypeId {
+        (self.argument.type_id)()
+    }
+    #[inline]
+    /// The name of the argument type `A` this entry was stored for, see [`core::any::type_name`].
+    pub fn type_name(&self) -> &'static str {
+        (self.argument.type_name)()
     }
     #[inline]
-    fn inner_mut(&mut self) -> &mut I {
-        &mut self.inner
+    /// Check if the entry was stored for the argument type `A`.
+    pub fn is<A: 'static + ?Sized>(&self) -> bool {
+        self.type_id() == TypeId::of::<A>()
+    }
+    #[inline]
+    /// Get the internal storage object of the entry.
+    pub fn storage(&self) -> &I {
+        self.inner()
+    }
+    #[inline]
+    /// Get the internal storage object of the entry mutably.
+    ///
+    /// # Safety
+    ///
+    /// Unsafe if the mutable reference is used to modify the hash for the key of the entry.
+    pub unsafe fn storage_mut(&mut self) -> &mut I {
+        self.inner_mut()
     }
 }
 // E is a nominal family describing the entries
-impl<E: 'static + ?Sized, I: ?Sized + RefAny> RawEntry<E, I> {
+impl<E: 'static + ?Sized, I: ?Sized + RefAny, const N: usize> ErasedEntry<E, I, N> {
     #[inline]
-    fn downcast<A: 'static + ?Sized>(self) -> Option<InnerEntry<E, A>>
+    pub(crate) fn downcast<A: 'static + ?Sized>(self) -> Option<InnerEntry<E, A>>
     where
         E: EntryFamily<A>,
     {
-        let inner: Box<I> = self.inner;
-        match inner.any_box().downcast::<InnerEntry<E, A>>() {
-            Result::Ok(k) => Some(*k),
-            // FIXME: throwing away information probably not a good idea
-            // Alas, there doesn't seem to be a way to go back to Box<dyn HashableAny<H>>.
-            Result::Err(_) => None,
+        // Use `is` to check beforehand if the entry should be kept on failure.
+        if !self.inner().any_ref().is::<InnerEntry<E, A>>() {
+            return None;
         }
+        // checked the type of the stored entry above
+        Some(unsafe { self.inner.take::<InnerEntry<E, A>>() })
     }
 
     #[inline]
-    fn downcast_ref<A: 'static + ?Sized>(&self) -> Option<&InnerEntry<E, A>>
+    pub(crate) fn downcast_ref<A: 'static + ?Sized>(&self) -> Option<&InnerEntry<E, A>>
     where
         E: EntryFamily<A>,
     {
@@ -370,7 +493,7 @@ impl<E: 'static + ?Sized, I: ?Sized + RefAny> RawEntry<E, I> {
     }
 
     #[inline]
-    fn downcast_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut InnerEntry<E, A>>
+    pub(crate) fn downcast_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut InnerEntry<E, A>>
     where
         E: EntryFamily<A>,
     {
@@ -378,180 +501,601 @@ impl<E: 'static + ?Sized, I: ?Sized + RefAny> RawEntry<E, I> {
             .any_mut()
             .downcast_mut::<InnerEntry<E, A>>()
     }
-}
 
-impl<E: ?Sized, I: ?Sized> RawEntry<E, I> {
     #[inline]
-    fn new<A: ?Sized>(hash: u64, entry: EntryAt<E, A>) -> Self
+    /// Get the pair of (key, value) of this entry, if it was stored for the argument type `A`.
+    pub fn hash_entry<A: 'static + ?Sized>(&self) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.downcast_ref::<A>() {
+            Some(inner) => Some(inner),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Get the value of this entry, if it was stored for the argument type `A`.
+    pub fn get_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.downcast_mut::<A>() {
+            Some(inner) => Some(inner.value_mut()),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Take ownership of the pair of (key, value) of this entry, if it was stored for the
+    /// argument type `A`. Otherwise, the erased entry is returned unchanged.
+    pub fn into_hash_entry<A: 'static + ?Sized>(self) -> Result<EntryAt<E, A>, Self>
     where
         E: EntryFamily<A>,
+    {
+        if !self.is::<A>() {
+            return Err(self);
+        }
+        match self.downcast() {
+            Some(inner) => Ok(inner.entry),
+            None => unreachable_internal_invariant("the argument type is stored correctly"),
+        }
+    }
+}
+
+impl<E: ?Sized, I: ?Sized, const N: usize> ErasedEntry<E, I, N> {
+    #[inline]
+    pub(crate) fn new<A: 'static + ?Sized>(hash: u64, entry: EntryAt<E, A>) -> Self
+    where
+        E: 'static + EntryFamily<A>,
+        I: CreateEntry<A, E>,
+    {
+        Self {
+            inner: Storage::new::<A, E>(entry),
+            hash,
+            argument: ArgumentType::of::<A>(),
+            _marker: core::marker::PhantomData,
+        }
+    }
+    // Unsafety: the arena must not be reset or dropped before the entry is dropped
+    #[cfg(feature = "bumpalo")]
+    #[inline]
+    pub(crate) unsafe fn new_in<A: 'static + ?Sized>(
+        hash: u64,
+        entry: EntryAt<E, A>,
+        arena: &bumpalo::Bump,
+    ) -> Self
+    where
+        E: 'static + EntryFamily<A>,
         I: CreateEntry<A, E>,
     {
         Self {
-            inner: I::from_entry(entry),
+            inner: Storage::new_in::<A, E>(entry, arena),
             hash,
-            _marker: std::marker::PhantomData,
+            argument: ArgumentType::of::<A>(),
+            _marker: core::marker::PhantomData,
         }
     }
 }
 
-/// The default [`BuildHasher`] used in the map, i.e. [`hashbrown::hash_map::DefaultHashBuilder`].
-pub type DefaultHashBuilder = hashbrown::hash_map::DefaultHashBuilder;
-/// The Hasher type corresponding to [`DefaultHashBuilder`]
-pub type DefaultHasher = <DefaultHashBuilder as BuildHasher>::Hasher;
-
-type DynStorage<S> = dyn HashableAny<<S as BuildHasher>::Hasher>;
-crate::create_entry_impl!(HashableAny<H>);
-
-/// A hash map implemented with quadratic probing and SIMD lookup.
-///
-/// The type of the entry stored in the map depends on the type of key used.
-/// For this to work, `E` - the entry family - should implement [`EntryFamily`] for each type of
-/// argument you want to store in the map.
+/// Mutable access to an [`ErasedEntry`] in a map, as returned when iterating mutably.
 ///
-pub struct Map<
-    E: ?Sized,
-    S: BuildHasher = DefaultHashBuilder,
-    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
-> {
-    // IMPORTANT: we cache the hash in each RawEntry, so the hash_state can not change without
-    // rehashing all items. Dynamic dispatch via HashableAny<S::Hasher> would then be needed,
-    // which is also how PartialEq works.
-    hash_state: S,
-    raw: RawTable<RawEntry<E, I>>,
+/// The value of the entry can be modified, but the entry itself can not be moved or replaced,
+/// since the map relies on the hash cached in the entry to find it again.
+/// Dereferences to the [`ErasedEntry`] for read-only access.
+pub struct ErasedEntryMut<'a, E: ?Sized, I: ?Sized, const N: usize = DEFAULT_INLINE_WORDS> {
+    entry: &'a mut ErasedEntry<E, I, N>,
 }
 
-/// An occupied entry in an [`Map`], containing the key that was used during lookup and the
-/// bucket where the entry is placed in the map. Can save on repeated lookups of the same
-/// key in some scenarios, but users should usually prefer the direct api of the map.
-pub struct OccupiedEntry<
-    'a,
-    A: ?Sized,
-    E: ?Sized + EntryFamily<A>,
-    S: BuildHasher,
-    I: ?Sized + HashableAny<S::Hasher>,
-> {
-    _hash: u64,
-    key: KeyAt<E, A>,
-    elem: Bucket<RawEntry<E, I>>,
-    table: &'a mut Map<E, S, I>,
+impl<'a, E: ?Sized, I: ?Sized, const N: usize> ErasedEntryMut<'a, E, I, N> {
+    #[inline]
+    pub(crate) fn new(entry: &'a mut ErasedEntry<E, I, N>) -> Self {
+        Self { entry }
+    }
+    #[inline]
+    /// Reborrow the entry, to pass it on without giving up access to it.
+    pub fn reborrow(&mut self) -> ErasedEntryMut<'_, E, I, N> {
+        ErasedEntryMut { entry: self.entry }
+    }
+    #[inline]
+    /// Get the internal storage object of the entry mutably.
+    ///
+    /// # Safety
+    ///
+    /// Unsafe if the mutable reference is used to modify the hash for the key of the entry.
+    pub unsafe fn storage_mut(&mut self) -> &mut I {
+        self.entry.storage_mut()
+    }
 }
 
-impl<
-        'a,
-        A: 'static + ?Sized,
-        E: 'static + ?Sized + EntryFamily<A>,
-        S: BuildHasher,
-        I: ?Sized + HashableAny<S::Hasher>,
-    > OccupiedEntry<'a, A, E, S, I>
-{
+impl<'a, E: 'static + ?Sized, I: ?Sized + RefAny, const N: usize> ErasedEntryMut<'a, E, I, N> {
     #[inline]
-    fn entry(&self) -> &InnerEntry<E, A> {
-        // holding a ref to the table, didn't rehash or reallocate
-        let inner_ref = unsafe { self.elem.as_ref() };
-        match inner_ref.downcast_ref() {
-            Some(r) => r,
-            // invariant of how we obtained the entry
-            None => unreachable_internal_invariant(
-                "the entry is constructed pointing only at correct types",
-            ),
+    /// Get the value of this entry, if it was stored for the argument type `A`.
+    pub fn get_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.entry.get_mut::<A>()
+    }
+    #[inline]
+    /// Convert into a mutable reference to the value of this entry, bound to the lifetime of
+    /// the map, if it was stored for the argument type `A`. Otherwise, the entry is returned.
+    pub fn into_mut<A: 'static + ?Sized>(self) -> Result<&'a mut ValueAt<E, A>, Self>
+    where
+        E: EntryFamily<A>,
+    {
+        match self.into_split_mut::<A>() {
+            Ok((_, value)) => Ok(value),
+            Err(entry) => Err(entry),
         }
     }
     #[inline]
-    fn entry_mut(&mut self) -> &mut InnerEntry<E, A> {
-        // holding a ref to the table, didn't rehash or reallocate
-        let inner_ref = unsafe { self.elem.as_mut() };
-        // FIXME: borrowing the whole entry can invalidate the key and hence the hash value
-        // in the map. How do we handle that?
-        match inner_ref.downcast_mut() {
-            Some(r) => r,
-            // invariant of how we obtained the entry
-            None => unreachable_internal_invariant(
-                "the entry is constructed pointing only at correct types",
-            ),
+    /// Convert into the key and a mutable reference to the value of this entry, bound to the
+    /// lifetime of the map, if it was stored for the argument type `A`. Otherwise, the entry
+    /// is returned.
+    #[allow(clippy::type_complexity)]
+    pub fn into_split_mut<A: 'static + ?Sized>(
+        self,
+    ) -> Result<(&'a KeyAt<E, A>, &'a mut ValueAt<E, A>), Self>
+    where
+        E: EntryFamily<A>,
+    {
+        if !self.entry.is::<A>() {
+            return Err(self);
+        }
+        match self.entry.downcast_mut::<A>() {
+            Some(inner) => Ok(inner.entry.split_mut()),
+            None => unreachable_internal_invariant("the argument type is stored correctly"),
         }
     }
+}
+
+impl<'a, E: ?Sized, I: ?Sized, const N: usize> Deref for ErasedEntryMut<'a, E, I, N> {
+    type Target = ErasedEntry<E, I, N>;
     #[inline]
-    /// Get the key used during lookup of the entry
-    pub fn key(&self) -> &KeyAt<E, A> {
-        // FIXME: technically we have two keys: the one used during lookup and the one stored in the map.
-        // maybe some apis want access to both?
-        &self.key
+    fn deref(&self) -> &Self::Target {
+        self.entry
     }
+}
+
+type ConvertFn<E, I, I2, const N: usize> = fn(ErasedEntry<E, I, N>) -> ErasedEntry<E, I2, N>;
+
+fn convert_entry<A: 'static + ?Sized, E: 'static + ?Sized, I: ?Sized + RefAny, I2: ?Sized, const N: usize>(
+    entry: ErasedEntry<E, I, N>,
+) -> ErasedEntry<E, I2, N>
+where
+    E: EntryFamily<A>,
+    I2: CreateEntry<A, E>,
+{
+    match entry.into_hash_entry::<A>() {
+        // The hash is recomputed with the hash state of the new map
+        Ok(entry) => ErasedEntry::new::<A>(0, entry),
+        Err(_) => unreachable_internal_invariant("the conversion is looked up by the argument type"),
+    }
+}
+
+/// The argument types whose entries [`Map::rehash_into`] re-creates in the storage type `I2`.
+///
+/// ```
+/// # use dependent_map::{Map, HashableAny, StorageConversion, families::Singleton};
+/// # use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault};
+/// type Deterministic = BuildHasherDefault<DefaultHasher>;
+/// let mut conversion = StorageConversion::<Singleton, _, dyn HashableAny<DefaultHasher>>::new();
+/// conversion.register::<u32>().register::<String>();
+///
+/// let mut map = Map::<Singleton>::new();
+/// let _ = map.insert(42u32);
+/// let map = map.rehash_into(Deterministic::default(), &conversion).unwrap();
+/// assert_eq!(map.get_default::<u32>().map(|e| e.some), Some(42));
+/// ```
+pub struct StorageConversion<E: ?Sized, I: ?Sized, I2: ?Sized, const N: usize = DEFAULT_INLINE_WORDS> {
+    converts: hashbrown::HashMap<TypeId, ConvertFn<E, I, I2, N>>,
+}
+
+impl<E: ?Sized, I: ?Sized, I2: ?Sized, const N: usize> StorageConversion<E, I, I2, N> {
     #[inline]
-    /// Get the pair of (key, value) found in the map for this entry.
-    pub fn hash_entry(&self) -> &EntryAt<E, A> {
-        self.entry()
+    /// Create a new conversion, without any registered argument types.
+    pub fn new() -> Self {
+        Self {
+            converts: hashbrown::HashMap::new(),
+        }
     }
     #[inline]
-    /// Get the pair of (key, value) found in the map for this entry.
-    ///
-    /// # Unsafe
-    ///
-    /// Unsafe if the mutable reference is used to modify the hash for the key of the entry.
-    pub unsafe fn hash_entry_mut(&mut self) -> &mut EntryAt<E, A> {
-        // FIXME: possibly should be unsafe, since it could modify the key (and thus the hashvalue) of the entry.
-        self.entry_mut()
+    /// The number of registered argument types.
+    pub fn len(&self) -> usize {
+        self.converts.len()
     }
     #[inline]
-    /// Get the value found in the map for this entry
-    pub fn get(&self) -> &ValueAt<E, A> {
-        self.entry().value()
+    /// Check if no argument type is registered.
+    pub fn is_empty(&self) -> bool {
+        self.converts.is_empty()
     }
     #[inline]
-    /// Get the value found in the map for this entry
-    pub fn get_mut(&mut self) -> &mut ValueAt<E, A> {
-        self.entry_mut().value_mut()
+    /// Check if the argument type `A` is registered.
+    pub fn contains<A: 'static + ?Sized>(&self) -> bool {
+        self.converts.contains_key(&TypeId::of::<A>())
     }
+}
+
+impl<E: 'static + ?Sized, I: ?Sized + RefAny, I2: ?Sized, const N: usize> StorageConversion<E, I, I2, N> {
     #[inline]
-    /// Replace the value found in the map for this entry and return the old value
-    pub fn insert(&mut self, value: ValueAt<E, A>) -> ValueAt<E, A> {
-        let place = self.get_mut();
-        std::mem::replace(place, value)
+    /// Register the argument type `A`, so that its entries can be converted.
+    pub fn register<A: 'static + ?Sized>(&mut self) -> &mut Self
+    where
+        E: EntryFamily<A>,
+        I2: CreateEntry<A, E>,
+    {
+        let _ = self
+            .converts
+            .insert(TypeId::of::<A>(), convert_entry::<A, E, I, I2, N>);
+        self
     }
+}
+
+impl<E: ?Sized, I: ?Sized, I2: ?Sized, const N: usize> Default for StorageConversion<E, I, I2, N> {
     #[inline]
-    /// Remove and return the entry from the map
-    pub fn remove_entry(self) -> EntryAt<E, A> {
-        // holding a ref to the table, didn't rehash or reallocate
-        let raw = unsafe { self.table.raw.remove(self.elem) };
-        match raw.downcast() {
-            Some(r) => r,
-            // invariant of how we obtained the entry
-            None => unreachable_internal_invariant(
-                "the entry is constructed pointing only at correct types",
-            ),
-        }
-        .entry
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-/// A vacant entry in an [`Map`].
-pub struct VacantEntry<
-    'a,
-    A: ?Sized,
-    E: ?Sized + EntryFamily<A>,
+impl<E: ?Sized, I: ?Sized, I2: ?Sized, const N: usize> Debug for StorageConversion<E, I, I2, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("StorageConversion")
+            .field("len", &self.converts.len())
+            .finish()
+    }
+}
+
+/// The error returned by [`Map::rehash_into`] when the map contains entries of an argument type
+/// that is not registered for the conversion.
+pub struct UnconvertedError<
+    E: ?Sized,
     S: BuildHasher,
     I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
 > {
-    hash: u64,
-    key: KeyAt<E, A>,
-    table: &'a mut Map<E, S, I>,
+    /// The map, unchanged.
+    pub map: Map<E, S, I, N>,
+    /// The name of the argument type that is not registered, see [`ErasedEntry::type_name`].
+    pub type_name: &'static str,
 }
 
-impl<
-        'a,
-        A: 'static + ?Sized,
-        E: 'static + ?Sized + EntryFamily<A>,
-        S: BuildHasher,
-        I: ?Sized + HashableAny<S::Hasher>,
-    > VacantEntry<'a, A, E, S, I>
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> Debug
+    for UnconvertedError<E, S, I, N>
 {
-    #[inline]
-    /// Get the key that was used during lookup
-    pub fn key(&self) -> &KeyAt<E, A> {
-        &self.key
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("UnconvertedError")
+            .field("type_name", &self.type_name)
+            .finish_non_exhaustive()
     }
-    #[inline]
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> core::fmt::Display
+    for UnconvertedError<E, S, I, N>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} is not registered for the conversion", self.type_name)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> std::error::Error
+    for UnconvertedError<E, S, I, N>
+{
+}
+
+/// The error returned by [`Map::try_insert`] when the key is already present.
+///
+/// Contains the occupied entry, and the entry that was not inserted.
+pub struct OccupiedError<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    /// The entry in the map that was already occupied.
+    pub entry: OccupiedEntry<'a, A, E, S, I, N>,
+    /// The entry which was not inserted, because the key was already occupied.
+    pub value: EntryAt<E, A>,
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > Debug for OccupiedError<'a, A, E, S, I, N>
+where
+    KeyAt<E, A>: Debug,
+    ValueAt<E, A>: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OccupiedError")
+            .field("key", self.entry.key())
+            .field("old_value", self.entry.get())
+            .field("new_value", self.value.split_ref().1)
+            .finish()
+    }
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > core::fmt::Display for OccupiedError<'a, A, E, S, I, N>
+where
+    KeyAt<E, A>: Debug,
+    ValueAt<E, A>: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "failed to insert {:?}, key {:?} already exists with value {:?}",
+            self.value.split_ref().1,
+            self.entry.key(),
+            self.entry.get(),
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > std::error::Error for OccupiedError<'a, A, E, S, I, N>
+where
+    KeyAt<E, A>: Debug,
+    ValueAt<E, A>: Debug,
+{
+}
+
+/// The error returned by [`Map::try_reserve`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TryReserveError {
+    /// Error due to the computed capacity exceeding the map's maximum
+    /// (usually `isize::MAX` bytes).
+    CapacityOverflow,
+    /// The memory allocator returned an error
+    AllocError {
+        /// The layout of the allocation request that failed.
+        layout: core::alloc::Layout,
+    },
+}
+
+impl From<hashbrown::TryReserveError> for TryReserveError {
+    #[inline]
+    fn from(e: hashbrown::TryReserveError) -> Self {
+        match e {
+            hashbrown::TryReserveError::CapacityOverflow => TryReserveError::CapacityOverflow,
+            hashbrown::TryReserveError::AllocError { layout } => {
+                TryReserveError::AllocError { layout }
+            }
+        }
+    }
+}
+
+impl core::fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str("memory allocation failed")?;
+        match self {
+            TryReserveError::CapacityOverflow => {
+                f.write_str(" because the computed capacity exceeded the map's maximum")
+            }
+            TryReserveError::AllocError { .. } => {
+                f.write_str(" because the memory allocator returned an error")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TryReserveError {}
+
+/// The default number of words an entry may take up to be stored inline in the table of a map.
+///
+/// Larger entries, and entries with an alignment greater than that of `usize`, are boxed up.
+/// See the `N` parameter of [`Map`] to change the inline capacity.
+pub const DEFAULT_INLINE_WORDS: usize = 3;
+
+/// The default [`BuildHasher`] used in the map, i.e. [`hashbrown::hash_map::DefaultHashBuilder`].
+pub type DefaultHashBuilder = hashbrown::hash_map::DefaultHashBuilder;
+/// The Hasher type corresponding to [`DefaultHashBuilder`]
+pub type DefaultHasher = <DefaultHashBuilder as BuildHasher>::Hasher;
+
+pub(crate) type DynStorage<S> = dyn HashableAny<<S as BuildHasher>::Hasher>;
+crate::create_entry_impl!(HashableAny<H>);
+
+/// A hash map implemented with quadratic probing and SIMD lookup.
+///
+/// The type of the entry stored in the map depends on the type of key used.
+/// For this to work, `E` - the entry family - should implement [`EntryFamily`] for each type of
+/// argument you want to store in the map.
+///
+/// Entries taking up at most `N` words are stored inline in the table, larger entries are boxed
+/// up. A larger `N` saves allocations at the cost of a larger table, see [`DEFAULT_INLINE_WORDS`].
+///
+pub struct Map<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    // IMPORTANT: we cache the hash in each ErasedEntry, so the hash_state can not change without
+    // rehashing all items. Dynamic dispatch via HashableAny<S::Hasher> is then needed,
+    // which is how `rehash_with` and PartialEq work.
+    hash_state: S,
+    raw: RawTable<ErasedEntry<E, I, N>>,
+}
+
+/// An occupied entry in an [`Map`], containing the key that was used during lookup and the
+/// bucket where the entry is placed in the map. Can save on repeated lookups of the same
+/// key in some scenarios, but users should usually prefer the direct api of the map.
+pub struct OccupiedEntry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    _hash: u64,
+    elem: Bucket<ErasedEntry<E, I, N>>,
+    table: &'a mut Map<E, S, I, N>,
+    _marker: NonOwningPhantomPointer<A>,
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > OccupiedEntry<'a, A, E, S, I, N>
+{
+    #[inline]
+    fn entry(&self) -> &InnerEntry<E, A> {
+        // holding a ref to the table, didn't rehash or reallocate
+        let inner_ref = unsafe { self.elem.as_ref() };
+        match inner_ref.downcast_ref() {
+            Some(r) => r,
+            // invariant of how we obtained the entry
+            None => unreachable_internal_invariant(
+                "the entry is constructed pointing only at correct types",
+            ),
+        }
+    }
+    #[inline]
+    fn entry_mut(&mut self) -> &mut InnerEntry<E, A> {
+        // holding a ref to the table, didn't rehash or reallocate
+        let inner_ref = unsafe { self.elem.as_mut() };
+        // FIXME: borrowing the whole entry can invalidate the key and hence the hash value
+        // in the map. How do we handle that?
+        match inner_ref.downcast_mut() {
+            Some(r) => r,
+            // invariant of how we obtained the entry
+            None => unreachable_internal_invariant(
+                "the entry is constructed pointing only at correct types",
+            ),
+        }
+    }
+    #[inline]
+    /// Get the key of the entry stored in the map
+    pub fn key(&self) -> &KeyAt<E, A> {
+        self.entry().key()
+    }
+    #[inline]
+    /// Converts the entry into a mutable reference to the value in the map that is bound
+    /// to the lifetime of the map.
+    pub fn into_mut(self) -> &'a mut ValueAt<E, A> {
+        // holding a ref to the table, didn't rehash or reallocate
+        let inner_ref = unsafe { self.elem.as_mut() };
+        match inner_ref.downcast_mut() {
+            Some(r) => r.value_mut(),
+            // invariant of how we obtained the entry
+            None => unreachable_internal_invariant(
+                "the entry is constructed pointing only at correct types",
+            ),
+        }
+    }
+    #[inline]
+    /// Get the pair of (key, value) found in the map for this entry.
+    pub fn hash_entry(&self) -> &EntryAt<E, A> {
+        self.entry()
+    }
+    #[inline]
+    /// Get the pair of (key, value) found in the map for this entry.
+    ///
+    /// # Safety
+    ///
+    /// Unsafe if the mutable reference is used to modify the hash for the key of the entry.
+    pub unsafe fn hash_entry_mut(&mut self) -> &mut EntryAt<E, A> {
+        // FIXME: possibly should be unsafe, since it could modify the key (and thus the hashvalue) of the entry.
+        self.entry_mut()
+    }
+    #[inline]
+    /// Get the value found in the map for this entry
+    pub fn get(&self) -> &ValueAt<E, A> {
+        self.entry().value()
+    }
+    #[inline]
+    /// Get the value found in the map for this entry
+    pub fn get_mut(&mut self) -> &mut ValueAt<E, A> {
+        self.entry_mut().value_mut()
+    }
+    #[inline]
+    /// Replace the value found in the map for this entry and return the old value
+    pub fn insert(&mut self, value: ValueAt<E, A>) -> ValueAt<E, A> {
+        let place = self.get_mut();
+        core::mem::replace(place, value)
+    }
+    #[inline]
+    /// Remove the entry from the map and return its value
+    pub fn remove(self) -> ValueAt<E, A>
+    where
+        EntryAt<E, A>: Sized,
+    {
+        self.remove_entry().into_parts().1
+    }
+    #[inline]
+    /// Remove and return the entry from the map.
+    ///
+    /// Use [`HashEntry::into_parts`] to split it into the key and the value.
+    pub fn remove_entry(self) -> EntryAt<E, A> {
+        // holding a ref to the table, didn't rehash or reallocate
+        let raw = unsafe { self.table.raw.remove(self.elem) };
+        match raw.downcast() {
+            Some(r) => r,
+            // invariant of how we obtained the entry
+            None => unreachable_internal_invariant(
+                "the entry is constructed pointing only at correct types",
+            ),
+        }
+        .entry
+    }
+}
+
+/// A vacant entry in an [`Map`].
+pub struct VacantEntry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    hash: u64,
+    key: KeyAt<E, A>,
+    table: &'a mut Map<E, S, I, N>,
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > VacantEntry<'a, A, E, S, I, N>
+{
+    #[inline]
+    /// Get the key that was used during lookup
+    pub fn key(&self) -> &KeyAt<E, A> {
+        &self.key
+    }
+    #[inline]
     /// Take ownership of the key that was used during lookup
     pub fn into_key(self) -> KeyAt<E, A> {
         self.key
@@ -559,14 +1103,11 @@ impl<
     /// Insert an entry, taking ownership of the already supplied key.
     ///
     /// Returns mutable access to inserted value.
-    pub fn insert(
-        self,
-        value: impl 'a + FnOnce(KeyAt<E, A>) -> EntryAt<E, A>,
-    ) -> &'a mut ValueAt<E, A>
+    pub fn insert(self, value: impl FnOnce(KeyAt<E, A>) -> EntryAt<E, A>) -> &'a mut ValueAt<E, A>
     where
         I: CreateEntry<A, E>,
     {
-        let raw_entry = RawEntry::new(self.hash, value(self.key));
+        let raw_entry = ErasedEntry::new(self.hash, value(self.key));
         let hashfn = make_hasher(&self.table.hash_state);
         let ins_entry = self.table.raw.insert_entry(self.hash, raw_entry, hashfn);
         match ins_entry.downcast_mut() {
@@ -574,6 +1115,26 @@ impl<
             None => unreachable_internal_invariant("inserted type is correct"),
         }
     }
+    /// Insert an entry, taking ownership of the already supplied key.
+    ///
+    /// Returns an [`OccupiedEntry`] pointing at the inserted entry.
+    pub fn insert_entry(
+        self,
+        value: impl FnOnce(KeyAt<E, A>) -> EntryAt<E, A>,
+    ) -> OccupiedEntry<'a, A, E, S, I, N>
+    where
+        I: CreateEntry<A, E>,
+    {
+        let raw_entry = ErasedEntry::new(self.hash, value(self.key));
+        let hashfn = make_hasher(&self.table.hash_state);
+        let bucket = self.table.raw.insert(self.hash, raw_entry, hashfn);
+        OccupiedEntry {
+            _hash: self.hash,
+            elem: bucket,
+            table: self.table,
+            _marker: PhantomData,
+        }
+    }
     #[inline]
     /// Insert an entry, by converting the key into an entry.
     ///
@@ -594,24 +1155,116 @@ pub enum Entry<
     E: ?Sized + EntryFamily<A>,
     S: BuildHasher,
     I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
 > {
     #[allow(missing_docs)]
-    Occupied(OccupiedEntry<'a, A, E, S, I>),
+    Occupied(OccupiedEntry<'a, A, E, S, I, N>),
     #[allow(missing_docs)]
-    Vacant(VacantEntry<'a, A, E, S, I>),
+    Vacant(VacantEntry<'a, A, E, S, I, N>),
 }
 
-impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Map<E, S, I> {
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > Entry<'a, A, E, S, I, N>
+{
+    #[inline]
+    /// Get the key of this entry, either the one stored in the map or the one used during lookup.
+    pub fn key(&self) -> &KeyAt<E, A> {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting `default` if empty.
+    ///
+    /// Returns mutable access to the value in the entry.
+    pub fn or_insert(self, default: ValueAt<E, A>) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+    {
+        self.or_insert_with(|| default)
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the result of `default` if empty.
+    ///
+    /// Returns mutable access to the value in the entry.
+    pub fn or_insert_with(self, default: impl FnOnce() -> ValueAt<E, A>) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(|k| HashEntry::from_parts(k, default())),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the result of `default` if empty.
+    /// The key of the entry is passed to `default`.
+    ///
+    /// Returns mutable access to the value in the entry.
+    pub fn or_insert_with_key(
+        self,
+        default: impl FnOnce(&KeyAt<E, A>) -> ValueAt<E, A>,
+    ) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(|k| {
+                let value = default(&k);
+                HashEntry::from_parts(k, value)
+            }),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the default value if empty.
+    ///
+    /// Returns mutable access to the value in the entry.
+    pub fn or_default(self) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+        ValueAt<E, A>: Default,
+    {
+        self.or_insert_with(Default::default)
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting `default` if empty.
+    ///
+    /// Returns an [`OccupiedEntry`] pointing at the entry in the map.
+    pub fn or_insert_entry(self, default: ValueAt<E, A>) -> OccupiedEntry<'a, A, E, S, I, N>
+    where
+        I: CreateEntry<A, E>,
+    {
+        match self {
+            Entry::Occupied(e) => e,
+            Entry::Vacant(e) => e.insert_entry(|k| HashEntry::from_parts(k, default)),
+        }
+    }
+    #[inline]
+    /// Provides in-place mutable access to an occupied entry before any potential inserts into the map.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut ValueAt<E, A>)) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> Map<E, S, I, N> {
     #[inline]
     /// Create a new, empty, [`Map`].
     pub fn new() -> Self
     where
         S: Default,
     {
-        Self {
-            raw: RawTable::new(),
-            hash_state: S::default(),
-        }
+        Self::with_hasher(S::default())
     }
     #[inline]
     /// Create a new, empty, [`Map`] with a specified initial capacity.
@@ -619,12 +1272,31 @@ impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Map<E, S, I>
     where
         S: Default,
     {
+        Self::with_capacity_and_hasher(capacity, S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`Map`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self {
+            raw: RawTable::new(),
+            hash_state,
+        }
+    }
+    #[inline]
+    /// Create a new, empty, [`Map`] with a specified initial capacity, which will use the
+    /// given hash builder to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_state: S) -> Self {
         Self {
             raw: RawTable::with_capacity(capacity),
-            hash_state: S::default(),
+            hash_state,
         }
     }
     #[inline]
+    /// Get a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hash_state
+    }
+    #[inline]
     /// Get the capacity of the backing storage.
     pub fn capacity(&self) -> usize {
         self.raw.capacity()
@@ -644,21 +1316,86 @@ impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Map<E, S, I>
     pub fn clear(&mut self) {
         self.raw.clear()
     }
+    // Used to convert into the other map variants, which reuse the hash stored for each entry
+    pub(crate) fn into_raw_parts(self) -> (S, RawIntoIter<ErasedEntry<E, I, N>>) {
+        (self.hash_state, self.raw.into_iter())
+    }
+    // Used to convert back from the other map variants. An entry with the same key must not
+    // be present yet, and the hash of the entry must be computed with the hash state of the map.
+    pub(crate) fn insert_unique_erased(&mut self, entry: ErasedEntry<E, I, N>) {
+        let hashfn = make_hasher(&self.hash_state);
+        let _ = self.raw.insert(entry.hash, entry, hashfn);
+    }
+    /// Move all entries into a map using a different hash builder, rehashing each of them.
+    ///
+    /// The storage type `I` has to be hashable with the hasher of both hash builders. This is always
+    /// the case if both produce the same type of hasher, e.g. for differently seeded instances.
+    /// Otherwise, use [`Map::rehash_into`].
+    pub fn rehash_with<S2: BuildHasher>(self, hash_state: S2) -> Map<E, S2, I, N>
+    where
+        I: HashableAny<S2::Hasher>,
+    {
+        let mut raw = RawTable::with_capacity(self.raw.len());
+        for mut entry in self.raw.into_iter() {
+            entry.hash = hash_def_entry(&hash_state, entry.inner());
+            let hashfn = make_hasher(&hash_state);
+            let _ = raw.insert(entry.hash, entry, hashfn);
+        }
+        Map { hash_state, raw }
+    }
+    /// Move all entries into a map using a different hash builder and storage type, rehashing
+    /// each of them.
+    ///
+    /// Unlike [`Map::rehash_with`], this works for hash builders producing different types of
+    /// hashers, e.g. to move between a DoS-resistant and a fast deterministic hasher, since the
+    /// storage type depends on the hasher. Each entry is re-created in the storage type `I2`,
+    /// which requires its argument type to be registered with `conversion`.
+    ///
+    /// If the map contains entries of an argument type that is not registered, the map is
+    /// returned unchanged in the error.
+    pub fn rehash_into<S2: BuildHasher, I2: ?Sized + HashableAny<S2::Hasher>>(
+        self,
+        hash_state: S2,
+        conversion: &StorageConversion<E, I, I2, N>,
+    ) -> Result<Map<E, S2, I2, N>, UnconvertedError<E, S, I, N>>
+    where
+        E: 'static,
+    {
+        let unconverted = self
+            .iter()
+            .find(|&entry| !conversion.converts.contains_key(&entry.type_id()))
+            .map(ErasedEntry::type_name);
+        if let Some(type_name) = unconverted {
+            return Err(UnconvertedError { map: self, type_name });
+        }
+        let mut map = Map::with_capacity_and_hasher(self.raw.len(), hash_state);
+        for entry in self.raw.into_iter() {
+            let convert = match conversion.converts.get(&entry.type_id()) {
+                Some(&convert) => convert,
+                None => unreachable_internal_invariant("all argument types are checked above"),
+            };
+            let mut entry = convert(entry);
+            entry.hash = hash_def_entry(&map.hash_state, entry.inner());
+            map.insert_unique_erased(entry);
+        }
+        Ok(map)
+    }
 }
 
 #[inline]
-fn hash_def_entry<S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>>(
+pub(crate) fn hash_def_entry<S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>>(
     state: &S,
     e: &I,
 ) -> u64 {
     let mut hasher = state.build_hasher();
     e.any_ref().type_id().hash(&mut hasher);
-    e.specific_hash(&mut hasher);
+    // Fully qualified, since `I` might be hashable with more than one type of hasher
+    HashableAny::<S::Hasher>::specific_hash(e, &mut hasher);
     hasher.finish()
 }
 
 #[inline]
-fn hash_def_key<
+pub(crate) fn hash_def_key<
     Q: ?Sized + Hash,
     A: 'static + ?Sized,
     E: 'static + ?Sized + EntryFamily<A>,
@@ -673,9 +1410,9 @@ fn hash_def_key<
     hasher.finish()
 }
 
-fn equivalent_key<A: 'static + ?Sized, E: 'static + ?Sized, Q: ?Sized + Eq, I: ?Sized + RefAny>(
+pub(crate) fn equivalent_key<A: 'static + ?Sized, E: 'static + ?Sized, Q: ?Sized + Eq, I: ?Sized + RefAny, const N: usize>(
     key: &Q,
-) -> impl '_ + FnMut(&RawEntry<E, I>) -> bool
+) -> impl '_ + FnMut(&ErasedEntry<E, I, N>) -> bool
 where
     E: EntryFamily<A>,
     KeyAt<E, A>: Borrow<Q>,
@@ -690,19 +1427,19 @@ where
 }
 
 // Deep equivalence comparison, not simply comparing the key
-fn equivalent_entry<E: 'static + ?Sized, I: ?Sized + RefAny + DynPartialEq>(
-    lhs: &RawEntry<E, I>,
-) -> impl '_ + Fn(&RawEntry<E, I>) -> bool {
-    move |rhs| lhs.inner.eq_dyn((*rhs.inner).any_ref())
+pub(crate) fn equivalent_entry<E: 'static + ?Sized, I: ?Sized + RefAny + DynPartialEq, const N: usize>(
+    lhs: &ErasedEntry<E, I, N>,
+) -> impl '_ + Fn(&ErasedEntry<E, I, N>) -> bool {
+    move |rhs| lhs.inner().eq_dyn(rhs.inner().any_ref())
 }
 
-fn make_hasher<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>>(
+pub(crate) fn make_hasher<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>(
     _state: &S,
-) -> impl '_ + Fn(&RawEntry<E, I>) -> u64 {
-    move |val: &RawEntry<E, I>| val.hash
+) -> impl '_ + Fn(&ErasedEntry<E, I, N>) -> u64 {
+    move |val: &ErasedEntry<E, I, N>| val.hash
 }
 
-impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Map<E, S, I> {
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> Map<E, S, I, N> {
     #[inline]
     fn hash_key<A: 'static + ?Sized, Q: ?Sized + Hash>(&self, key: &Q) -> u64
     where
@@ -765,6 +1502,35 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
         self.get_inner_mut_by_hash(hash, key)
     }
 
+    #[inline]
+    fn find_bucket<A: 'static + ?Sized, Q: ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Option<Bucket<ErasedEntry<E, I, N>>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, _>(key);
+        self.raw.find(hash, equivalent_key(key))
+    }
+
+    // Unsafety: the bucket must have been found for the argument type `A` in this map, and no
+    // other reference to the entry may be live for `'a`.
+    #[inline]
+    unsafe fn bucket_value_mut<'a, A: 'static + ?Sized>(
+        bucket: &Bucket<ErasedEntry<E, I, N>>,
+    ) -> &'a mut ValueAt<E, A>
+    where
+        E: EntryFamily<A>,
+    {
+        match bucket.as_mut().downcast_mut() {
+            Some(inner) => inner.value_mut(),
+            None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        }
+    }
+
     #[inline]
     /// Reserve storage to fit at least `additional` more entries without reallocating.
     pub fn reserve(&mut self, additional: usize) {
@@ -772,6 +1538,17 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
         self.raw.reserve(additional, hashfn)
     }
     #[inline]
+    /// Try to reserve storage to fit at least `additional` more entries without reallocating.
+    ///
+    /// Unlike [`Map::reserve`], this returns an error instead of aborting if the allocation fails.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let hashfn = make_hasher(&self.hash_state);
+        match self.raw.try_reserve(additional, hashfn) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    #[inline]
     /// Shrink storage to fit the currently used size.
     pub fn shrink_to_fit(&mut self) {
         let hashfn = make_hasher(&self.hash_state);
@@ -779,7 +1556,7 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
     }
     #[inline]
     /// Lookup the entry at `key`.
-    pub fn entry<A: 'static + ?Sized>(&mut self, key: KeyAt<E, A>) -> Entry<'_, A, E, S, I>
+    pub fn entry<A: 'static + ?Sized>(&mut self, key: KeyAt<E, A>) -> Entry<'_, A, E, S, I, N>
     where
         E: EntryFamily<A>,
     {
@@ -787,9 +1564,9 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
         match self.raw.find(hash, equivalent_key(&key)) {
             Some(bucket) => Entry::Occupied(OccupiedEntry {
                 _hash: hash,
-                key,
                 elem: bucket,
                 table: self,
+                _marker: PhantomData,
             }),
             None => Entry::Vacant(VacantEntry {
                 hash,
@@ -799,6 +1576,15 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
         }
     }
     #[inline]
+    /// Lookup the entry at the default key.
+    pub fn entry_default<A: 'static + ?Sized>(&mut self) -> Entry<'_, A, E, S, I, N>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.entry(Default::default())
+    }
+    #[inline]
     /// Check if the map contains a value for the specified key.
     pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
     where
@@ -809,6 +1595,15 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
         self.get_inner(k).is_some()
     }
     #[inline]
+    /// Check if the map contains a value for the default key.
+    pub fn contains_default<A: 'static + ?Sized>(&self) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.contains_key::<A, _>(&Default::default())
+    }
+    #[inline]
     /// Returns a reference to the value corresponding to the key.
     pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<&EntryAt<E, A>>
     where
@@ -845,29 +1640,130 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
             None => None,
         }
     }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the default key.
+    pub fn get_default_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get_mut::<A, _>(&Default::default())
+    }
+    /// Returns mutable references to several values of different argument types at once.
+    ///
+    /// `T` is a tuple of argument types, such as `(A, B, C)`, and `keys` a tuple of references
+    /// to the respective keys. Returns [`None`] if any of the keys is missing, or if two of the
+    /// keys refer to the same entry.
+    pub fn get_many_mut<'k, T: ArgumentTuple<E>>(
+        &mut self,
+        keys: T::Keys<'k>,
+    ) -> Option<T::ValuesMut<'_>> {
+        T::get_many_mut(self, keys)
+    }
+    /// Returns mutable references to the values at several keys of the same argument type at once.
+    ///
+    /// Returns [`None`] if any of the keys is missing, or if two of the keys refer to the same entry.
+    pub fn get_many_key_mut<A: 'static + ?Sized, Q: ?Sized, const K: usize>(
+        &mut self,
+        keys: [&Q; K],
+    ) -> Option<[&mut ValueAt<E, A>; K]>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let buckets: [Option<Bucket<ErasedEntry<E, I, N>>>; K] =
+            core::array::from_fn(|i| self.find_bucket::<A, Q>(keys[i]));
+        for (i, bucket) in buckets.iter().enumerate() {
+            let bucket = bucket.as_ref()?;
+            if buckets[..i]
+                .iter()
+                .flatten()
+                .any(|b| b.as_ptr() == bucket.as_ptr())
+            {
+                return None;
+            }
+        }
+        Some(core::array::from_fn(|i| match &buckets[i] {
+            // Unsafety: all buckets are present and pairwise distinct, the map is borrowed mutably
+            Some(bucket) => unsafe { Self::bucket_value_mut::<A>(bucket) },
+            None => unreachable_internal_invariant("all buckets were checked to be present"),
+        }))
+    }
     /// Inserts an entry into the map.
     ///
     /// If the map did not have this key present, [`None`] is returned.
     ///
     /// Otherwise, the entry is fully replaced and `Some(old)` where `old` is the old entry is returned.
-    pub fn insert<A: 'static + ?Sized, P: ?Sized>(&mut self, entry: P) -> Option<EntryAt<E, A>>
+    pub fn insert<A: 'static + ?Sized, P>(&mut self, entry: P) -> Option<EntryAt<E, A>>
     where
         E: EntryFamily<A>,
         I: CreateEntry<A, E>,
         P: Into<EntryAt<E, A>>,
     {
-        let entry = entry.into();
+        self.insert_with(entry.into(), ErasedEntry::new::<A>)
+    }
+    // Like `insert`, but `erase` decides how a new entry is stored
+    #[inline]
+    pub(crate) fn insert_with<A: 'static + ?Sized>(
+        &mut self,
+        entry: EntryAt<E, A>,
+        erase: impl FnOnce(u64, EntryAt<E, A>) -> ErasedEntry<E, I, N>,
+    ) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
         let key = entry.split_ref().0;
         let hash = self.hash_key(key);
         if let Some(existing) = self.get_inner_mut_by_hash(hash, key) {
-            Some(std::mem::replace(&mut existing.entry, entry))
+            Some(core::mem::replace(&mut existing.entry, entry))
         } else {
-            let raw_entry = RawEntry::<E, I>::new(hash, entry);
+            let raw_entry = erase(hash, entry);
             let hashfn = make_hasher(&self.hash_state);
             let _ = self.raw.insert(hash, raw_entry, hashfn);
             None
         }
     }
+    /// Tries to insert an entry into the map.
+    ///
+    /// If the map did not have this key present, the entry is inserted and mutable access to
+    /// the inserted value is returned.
+    ///
+    /// Otherwise, nothing is updated, and an error containing the occupied entry and the
+    /// rejected entry is returned.
+    pub fn try_insert<A: 'static + ?Sized, P>(
+        &mut self,
+        entry: P,
+    ) -> Result<&mut ValueAt<E, A>, OccupiedError<'_, A, E, S, I, N>>
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let entry = entry.into();
+        let key = entry.split_ref().0;
+        let hash = self.hash_key(key);
+        match self.raw.find(hash, equivalent_key(key)) {
+            Some(bucket) => Err(OccupiedError {
+                entry: OccupiedEntry {
+                    _hash: hash,
+                    elem: bucket,
+                    table: self,
+                    _marker: PhantomData,
+                },
+                value: entry,
+            }),
+            None => {
+                let raw_entry = ErasedEntry::<E, I, N>::new(hash, entry);
+                let hashfn = make_hasher(&self.hash_state);
+                let ins_entry = self.raw.insert_entry(hash, raw_entry, hashfn);
+                match ins_entry.downcast_mut() {
+                    Some(m) => Ok(m.value_mut()),
+                    None => unreachable_internal_invariant("inserted type is correct"),
+                }
+            }
+        }
+    }
     /// Removes a key from the map, returning the value at the key if the key
     /// was previously in the map.
     pub fn remove_entry<A: 'static + ?Sized, Q: ?Sized>(&mut self, key: &Q) -> Option<EntryAt<E, A>>
@@ -886,9 +1782,19 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
             None => None,
         }
     }
-    /*
-    // FIXME: add split method to Entry that returns values instead of references?
-    //  dubious anyway, since that throws away the key
+    #[inline]
+    /// Removes the default key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    pub fn remove_default<A: 'static + ?Sized>(&mut self) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.remove_entry::<A, _>(&Default::default())
+    }
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    #[allow(clippy::manual_map)]
     pub fn remove<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<ValueAt<E, A>>
     where
         E: EntryFamily<A>,
@@ -896,43 +1802,408 @@ impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Ma
         Q: Hash + Eq,
     {
         // Avoid `Option::map` because it bloats LLVM IR.
-        match self.remove_entry(k) {
-            Some(v) => Some(v),
+        match self.remove_entry::<A, Q>(k) {
+            Some(v) => Some(v.into_parts().1),
             None => None,
         }
     }
-    */
-    fn iter(&self) -> impl '_ + Iterator<Item = &'_ RawEntry<E, I>> {
-        // Unsafety: lifetime is captured, so map must outlive it
-        let it = unsafe { self.raw.iter() };
-        it.map(|b| {
-            // Unsafety: borrow is active, so Bucket outlives it
-            unsafe { b.as_ref() }
+    #[inline]
+    /// An iterator visiting all entries in arbitrary order.
+    pub fn iter(&self) -> Iter<'_, E, I, N> {
+        Iter {
+            // Unsafety: lifetime is captured, so map must outlive it
+            inner: unsafe { self.raw.iter() },
+            _marker: PhantomData,
+        }
+    }
+    #[inline]
+    /// An iterator visiting all entries in arbitrary order, with mutable access to their values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, E, I, N> {
+        IterMut {
+            // Unsafety: lifetime is captured, so map must outlive it
+            inner: unsafe { self.raw.iter() },
+            _marker: PhantomData,
+        }
+    }
+    #[inline]
+    /// An iterator visiting all entries stored for the argument type `A` in arbitrary order.
+    ///
+    /// Note that this has to visit every entry in the map, so it takes time proportional to
+    /// the capacity of the map, not to the number of entries stored for `A`.
+    pub fn iter_of<A: 'static + ?Sized>(&self) -> impl '_ + Iterator<Item = &'_ EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter().filter_map(ErasedEntry::hash_entry::<A>)
+    }
+    #[inline]
+    /// An iterator visiting all entries stored for the argument type `A` in arbitrary order,
+    /// with mutable access to the values. See also [`Map::iter_of`].
+    pub fn iter_of_mut<A: 'static + ?Sized>(
+        &mut self,
+    ) -> impl '_ + Iterator<Item = (&'_ KeyAt<E, A>, &'_ mut ValueAt<E, A>)>
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter_mut().filter_map(|e| e.into_split_mut::<A>().ok())
+    }
+    #[inline]
+    /// An iterator visiting all keys stored for the argument type `A` in arbitrary order.
+    /// See also [`Map::iter_of`].
+    pub fn keys_of<A: 'static + ?Sized>(&self) -> impl '_ + Iterator<Item = &'_ KeyAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter_of::<A>().map(|e| e.split_ref().0)
+    }
+    #[inline]
+    /// An iterator visiting all values stored for the argument type `A` in arbitrary order.
+    /// See also [`Map::iter_of`].
+    pub fn values_of<A: 'static + ?Sized>(&self) -> impl '_ + Iterator<Item = &'_ ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter_of::<A>().map(|e| e.split_ref().1)
+    }
+    #[inline]
+    /// Count the entries stored for the argument type `A`. See also [`Map::iter_of`].
+    pub fn count_of<A: 'static + ?Sized>(&self) -> usize
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter().filter(|e| e.is::<A>()).count()
+    }
+    #[inline]
+    /// Check if the map contains any entry of argument type `A`, regardless of its key.
+    pub fn contains_type<A: 'static + ?Sized>(&self) -> bool
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter().any(|e| e.is::<A>())
+    }
+    #[inline]
+    /// Removes all entries of argument type `A`, regardless of their key.
+    ///
+    /// Returns the number of removed entries.
+    pub fn remove_type<A: 'static + ?Sized>(&mut self) -> usize
+    where
+        E: EntryFamily<A>,
+    {
+        let len = self.len();
+        self.retain(|e| !e.is::<A>());
+        len - self.len()
+    }
+    #[inline]
+    /// Removes all entries of argument type `A`, regardless of their key, and returns them.
+    pub fn take_type<A: 'static + ?Sized>(&mut self) -> Vec<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.drain_of::<A>().collect()
+    }
+    /// Retains only the entries specified by the predicate.
+    ///
+    /// In other words, remove all entries `e` for which `f(e)` returns `false`.
+    pub fn retain(&mut self, mut f: impl FnMut(ErasedEntryMut<'_, E, I, N>) -> bool) {
+        // Here we only use `iter` as a temporary, preventing use-after-free
+        unsafe {
+            for bucket in self.raw.iter() {
+                if !f(ErasedEntryMut::new(bucket.as_mut())) {
+                    self.raw.erase(bucket);
+                }
+            }
+        }
+    }
+    /// Retains only the entries of argument type `A` specified by the predicate.
+    /// Entries of other types are always retained.
+    ///
+    /// In other words, remove all entries `(k, v)` of type `A` for which `f(&k, &mut v)` returns `false`.
+    pub fn retain_of<A: 'static + ?Sized>(
+        &mut self,
+        mut f: impl FnMut(&KeyAt<E, A>, &mut ValueAt<E, A>) -> bool,
+    ) where
+        E: EntryFamily<A>,
+    {
+        self.retain(|e| match e.into_split_mut::<A>() {
+            Ok((key, value)) => f(key, value),
+            Err(_) => true,
         })
     }
-    fn _iter_mut(&mut self) -> impl '_ + Iterator<Item = &'_ mut RawEntry<E, I>> {
-        // Unsafety: lifetime is captured, so map must outlive it
-        let it = unsafe { self.raw.iter() };
-        it.map(|b| {
+    #[inline]
+    /// Clears the map, returning all entries as an iterator. Keeps the allocated memory for reuse.
+    ///
+    /// If the returned iterator is dropped before being fully consumed, it drops the remaining entries.
+    pub fn drain(&mut self) -> Drain<'_, E, I, N> {
+        Drain {
+            inner: self.raw.drain(),
+        }
+    }
+    #[inline]
+    /// Creates an iterator which uses a closure to determine if an entry should be removed.
+    ///
+    /// If the closure returns `true`, the entry is removed from the map and yielded.
+    /// If the closure returns `false`, the entry remains in the map and will not be yielded.
+    ///
+    /// If the returned iterator is dropped before being fully consumed, the entries that
+    /// were not yet visited are retained.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, E, I, F, N>
+    where
+        F: FnMut(ErasedEntryMut<'_, E, I, N>) -> bool,
+    {
+        ExtractIf {
+            // Unsafety: the table is mutably borrowed by the iterator, so it outlives it
+            iter: unsafe { self.raw.iter() },
+            table: &mut self.raw,
+            pred,
+        }
+    }
+    #[inline]
+    /// Removes all entries of argument type `A`, returning them as an iterator.
+    /// Entries of other types are retained.
+    ///
+    /// If the returned iterator is dropped before being fully consumed, the entries that
+    /// were not yet visited are retained, see [`Map::extract_if`].
+    pub fn drain_of<A: 'static + ?Sized>(&mut self) -> impl '_ + Iterator<Item = EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.extract_if(|e| e.is::<A>())
+            .map(|e| match e.into_hash_entry::<A>() {
+                Ok(entry) => entry,
+                Err(_) => {
+                    unreachable_internal_invariant("only entries of the correct type are extracted")
+                }
+            })
+    }
+}
+
+/// A tuple of argument types, used to access several entries of a [`Map`] at once.
+/// See [`Map::get_many_mut`].
+///
+/// This is implemented for tuples of up to eight argument types.
+pub trait ArgumentTuple<E: ?Sized> {
+    /// The tuple of references to the key of each argument type.
+    type Keys<'k>
+    where
+        E: 'k;
+    /// The tuple of mutable references to the value of each argument type.
+    type ValuesMut<'a>
+    where
+        E: 'a;
+    #[doc(hidden)]
+    fn get_many_mut<'a, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>(
+        map: &'a mut Map<E, S, I, N>,
+        keys: Self::Keys<'_>,
+    ) -> Option<Self::ValuesMut<'a>>;
+}
+
+macro_rules! argument_tuple_impl {
+    ($($A:ident $key:ident $bucket:ident),+) => {
+        impl<E: 'static + ?Sized, $($A: 'static),+> ArgumentTuple<E> for ($($A,)+)
+        where
+            $(E: EntryFamily<$A>,)+
+        {
+            type Keys<'k> = ($(&'k KeyAt<E, $A>,)+) where E: 'k;
+            type ValuesMut<'a> = ($(&'a mut ValueAt<E, $A>,)+) where E: 'a;
+            fn get_many_mut<'a, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>(
+                map: &'a mut Map<E, S, I, N>,
+                ($($key,)+): Self::Keys<'_>,
+            ) -> Option<Self::ValuesMut<'a>> {
+                $(let $bucket = map.find_bucket::<$A, _>($key)?;)+
+                let ptrs = [$($bucket.as_ptr() as *const u8),+];
+                for (i, ptr) in ptrs.iter().enumerate() {
+                    if ptrs[..i].contains(ptr) {
+                        return None;
+                    }
+                }
+                // Unsafety: all buckets are pairwise distinct, the map is borrowed mutably
+                Some(($(unsafe { Map::<E, S, I, N>::bucket_value_mut::<$A>(&$bucket) },)+))
+            }
+        }
+    };
+}
+
+argument_tuple_impl!(A0 k0 b0);
+argument_tuple_impl!(A0 k0 b0, A1 k1 b1);
+argument_tuple_impl!(A0 k0 b0, A1 k1 b1, A2 k2 b2);
+argument_tuple_impl!(A0 k0 b0, A1 k1 b1, A2 k2 b2, A3 k3 b3);
+argument_tuple_impl!(A0 k0 b0, A1 k1 b1, A2 k2 b2, A3 k3 b3, A4 k4 b4);
+argument_tuple_impl!(A0 k0 b0, A1 k1 b1, A2 k2 b2, A3 k3 b3, A4 k4 b4, A5 k5 b5);
+argument_tuple_impl!(A0 k0 b0, A1 k1 b1, A2 k2 b2, A3 k3 b3, A4 k4 b4, A5 k5 b5, A6 k6 b6);
+argument_tuple_impl!(A0 k0 b0, A1 k1 b1, A2 k2 b2, A3 k3 b3, A4 k4 b4, A5 k5 b5, A6 k6 b6, A7 k7 b7);
+
+/// A draining iterator over the entries of a [`Map`], see [`Map::drain`].
+pub struct Drain<'a, E: ?Sized, I: ?Sized, const N: usize = DEFAULT_INLINE_WORDS> {
+    inner: RawDrain<'a, ErasedEntry<E, I, N>>,
+}
+
+impl<E: ?Sized, I: ?Sized, const N: usize> Iterator for Drain<'_, E, I, N> {
+    type Item = ErasedEntry<E, I, N>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<E: ?Sized, I: ?Sized, const N: usize> ExactSizeIterator for Drain<'_, E, I, N> {}
+impl<E: ?Sized, I: ?Sized, const N: usize> FusedIterator for Drain<'_, E, I, N> {}
+
+/// An iterator removing the entries of a [`Map`] that match a predicate, see [`Map::extract_if`].
+pub struct ExtractIf<'a, E: ?Sized, I: ?Sized, F, const N: usize = DEFAULT_INLINE_WORDS> {
+    iter: RawIter<ErasedEntry<E, I, N>>,
+    table: &'a mut RawTable<ErasedEntry<E, I, N>>,
+    pred: F,
+}
+
+impl<E: ?Sized, I: ?Sized, F, const N: usize> Iterator for ExtractIf<'_, E, I, F, N>
+where
+    F: FnMut(ErasedEntryMut<'_, E, I, N>) -> bool,
+{
+    type Item = ErasedEntry<E, I, N>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        for bucket in &mut self.iter {
+            // Unsafety: the bucket has not been removed yet, and the table is borrowed
+            let entry = unsafe { bucket.as_mut() };
+            if (self.pred)(ErasedEntryMut::new(entry)) {
+                // Unsafety: the bucket is full and belongs to this table
+                return Some(unsafe { self.table.remove(bucket) });
+            }
+        }
+        None
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<E: ?Sized, I: ?Sized, F, const N: usize> FusedIterator for ExtractIf<'_, E, I, F, N> where
+    F: FnMut(ErasedEntryMut<'_, E, I, N>) -> bool
+{
+}
+
+/// An iterator over the entries of a [`Map`], see [`Map::iter`].
+pub struct Iter<'a, E: ?Sized, I: ?Sized, const N: usize = DEFAULT_INLINE_WORDS> {
+    inner: RawIter<ErasedEntry<E, I, N>>,
+    _marker: PhantomData<&'a ErasedEntry<E, I, N>>,
+}
+
+impl<'a, E: ?Sized, I: ?Sized, const N: usize> Iterator for Iter<'a, E, I, N> {
+    type Item = &'a ErasedEntry<E, I, N>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next() {
             // Unsafety: borrow is active, so Bucket outlives it
-            unsafe { b.as_mut() }
-        })
+            Some(b) => Some(unsafe { b.as_ref() }),
+            None => None,
+        }
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<E: ?Sized, I: ?Sized, const N: usize> ExactSizeIterator for Iter<'_, E, I, N> {}
+impl<E: ?Sized, I: ?Sized, const N: usize> FusedIterator for Iter<'_, E, I, N> {}
+
+/// A mutable iterator over the entries of a [`Map`], see [`Map::iter_mut`].
+pub struct IterMut<'a, E: ?Sized, I: ?Sized, const N: usize = DEFAULT_INLINE_WORDS> {
+    inner: RawIter<ErasedEntry<E, I, N>>,
+    _marker: PhantomData<&'a mut ErasedEntry<E, I, N>>,
+}
+
+impl<'a, E: ?Sized, I: ?Sized, const N: usize> Iterator for IterMut<'a, E, I, N> {
+    type Item = ErasedEntryMut<'a, E, I, N>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next() {
+            // Unsafety: borrow is active, so Bucket outlives it
+            Some(b) => Some(ErasedEntryMut::new(unsafe { b.as_mut() })),
+            None => None,
+        }
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<E: ?Sized, I: ?Sized, const N: usize> ExactSizeIterator for IterMut<'_, E, I, N> {}
+impl<E: ?Sized, I: ?Sized, const N: usize> FusedIterator for IterMut<'_, E, I, N> {}
+
+/// An owning iterator over the entries of a [`Map`].
+pub struct IntoIter<E: ?Sized, I: ?Sized, const N: usize = DEFAULT_INLINE_WORDS> {
+    inner: RawIntoIter<ErasedEntry<E, I, N>>,
+}
+
+impl<E: ?Sized, I: ?Sized, const N: usize> Iterator for IntoIter<E, I, N> {
+    type Item = ErasedEntry<E, I, N>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<E: ?Sized, I: ?Sized, const N: usize> ExactSizeIterator for IntoIter<E, I, N> {}
+impl<E: ?Sized, I: ?Sized, const N: usize> FusedIterator for IntoIter<E, I, N> {}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> IntoIterator for Map<E, S, I, N> {
+    type Item = ErasedEntry<E, I, N>;
+    type IntoIter = IntoIter<E, I, N>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            inner: self.raw.into_iter(),
+        }
+    }
+}
+
+impl<'a, E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> IntoIterator
+    for &'a Map<E, S, I, N>
+{
+    type Item = &'a ErasedEntry<E, I, N>;
+    type IntoIter = Iter<'a, E, I, N>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> IntoIterator
+    for &'a mut Map<E, S, I, N>
+{
+    type Item = ErasedEntryMut<'a, E, I, N>;
+    type IntoIter = IterMut<'a, E, I, N>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
     }
 }
 
-impl<E: ?Sized, I: ?Sized + DynClone> Clone for RawEntry<E, I> {
+impl<E: ?Sized, I: ?Sized + DynClone, const N: usize> Clone for ErasedEntry<E, I, N> {
     #[inline]
     fn clone(&self) -> Self {
         Self {
-            inner: dyn_clone::clone_box(&self.inner),
+            inner: self.inner.clone(),
             hash: self.hash,
-            _marker: std::marker::PhantomData,
+            argument: self.argument,
+            _marker: core::marker::PhantomData,
         }
     }
 }
 
-impl<E: ?Sized, S: BuildHasher + Clone, I: ?Sized + HashableAny<S::Hasher> + DynClone> Clone
-    for Map<E, S, I>
+impl<E: ?Sized, S: BuildHasher + Clone, I: ?Sized + HashableAny<S::Hasher> + DynClone, const N: usize> Clone
+    for Map<E, S, I, N>
 {
     #[inline]
     fn clone(&self) -> Self {
@@ -943,8 +2214,8 @@ impl<E: ?Sized, S: BuildHasher + Clone, I: ?Sized + HashableAny<S::Hasher> + Dyn
     }
 }
 
-impl<E: ?Sized, S: Default + BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Default
-    for Map<E, S, I>
+impl<E: ?Sized, S: Default + BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> Default
+    for Map<E, S, I, N>
 {
     #[inline]
     fn default() -> Self {
@@ -954,46 +2225,47 @@ impl<E: ?Sized, S: Default + BuildHasher, I: ?Sized + HashableAny<S::Hasher>> De
 
 impl<
         E: 'static + ?Sized,
-        S: Default + BuildHasher,
-        I: ?Sized + HashableAny<S::Hasher> + DynPartialEq,
-    > PartialEq for Map<E, S, I>
+        S: BuildHasher,
+        S2: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher> + HashableAny<S2::Hasher> + DynPartialEq,
+        const N: usize,
+    > PartialEq<Map<E, S2, I, N>> for Map<E, S, I, N>
 {
-    fn eq(&self, rhs: &Self) -> bool {
+    fn eq(&self, rhs: &Map<E, S2, I, N>) -> bool {
         if self.len() != rhs.len() {
             return false;
         }
 
-        // TODO: should this care about the different hash states? Probably not
+        // Entries are rehashed with the hash state of rhs, so the two maps need not share one.
         self.iter().all(|entry| {
             let rhash = hash_def_entry(&rhs.hash_state, entry.inner());
-            matches!(rhs.raw.get(rhash, equivalent_entry(entry)), Some(_))
-            // rhs.get_inner(key: &Q)
+            rhs.raw.get(rhash, equivalent_entry(entry)).is_some()
         })
     }
 }
 
-impl<E: 'static + ?Sized, S: Default + BuildHasher, I: ?Sized + HashableAny<S::Hasher> + DynEq> Eq
-    for Map<E, S, I>
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher> + DynEq, const N: usize> Eq
+    for Map<E, S, I, N>
 {
 }
 
-struct SomeKey<'a, E: ?Sized, I: ?Sized>(&'a RawEntry<E, I>);
-struct SomeValue<'a, E: ?Sized, I: ?Sized>(&'a RawEntry<E, I>);
-impl<'a, E: ?Sized, I: ?Sized + DebugEntry> Debug for SomeKey<'a, E, I> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+pub(crate) struct SomeKey<'a, E: ?Sized, I: ?Sized, const N: usize>(pub(crate) &'a ErasedEntry<E, I, N>);
+pub(crate) struct SomeValue<'a, E: ?Sized, I: ?Sized, const N: usize>(pub(crate) &'a ErasedEntry<E, I, N>);
+impl<'a, E: ?Sized, I: ?Sized + DebugEntry, const N: usize> Debug for SomeKey<'a, E, I, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         self.0.inner().fmt_key(f)
     }
 }
-impl<'a, E: ?Sized, I: ?Sized + DebugEntry> Debug for SomeValue<'a, E, I> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl<'a, E: ?Sized, I: ?Sized + DebugEntry, const N: usize> Debug for SomeValue<'a, E, I, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         self.0.inner().fmt_value(f)
     }
 }
 
-impl<E: 'static + ?Sized, S: Default + BuildHasher, I: ?Sized + HashableAny<S::Hasher> + DebugEntry>
-    Debug for Map<E, S, I>
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher> + DebugEntry, const N: usize> Debug
+    for Map<E, S, I, N>
 {
-    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> core::fmt::Result {
         fmt.debug_map()
             .entries(self.iter().map(|e| (SomeKey(e), SomeValue(e))))
             .finish()
@@ -0,0 +1,521 @@
+//! A [`TypeRegistry`], to serialize and deserialize a [`Map`] with [`serde`].
+//!
+//! Available with the `serde` feature. Since the types stored in a map are only known at
+//! runtime, each argument type has to be registered under a stable tag, together with the
+//! (de)serializer of its entry. A map is then serialized as a map from these tags to the
+//! sequence of entries stored for that argument type, e.g. in JSON:
+//!
+//! ```json
+//! { "counter": [{ "key": 1, "value": 10 }], "name": [{ "key": 0, "value": "server" }] }
+//! ```
+//!
+//! With the `snapshot` feature, maps can also be written to compact, versioned binary
+//! snapshots, see [`snapshot`].
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::TypeId;
+use core::fmt::{Debug, Formatter};
+use core::hash::BuildHasher;
+use hashbrown::HashMap;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use crate::{
+    unreachable_internal_invariant, CreateEntry, DefaultHashBuilder, DynStorage, EntryAt,
+    EntryFamily, ErasedEntry, HashableAny, Map, RefAny, DEFAULT_INLINE_WORDS,
+};
+
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+
+/// What a [`TypeRegistry`] does with argument types that are not registered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownTypes {
+    /// Fail with an error, the default.
+    Error,
+    /// Leave out entries of unregistered types when serializing, and ignore unknown tags
+    /// when deserializing.
+    Skip,
+}
+
+impl Default for UnknownTypes {
+    #[inline]
+    fn default() -> Self {
+        UnknownTypes::Error
+    }
+}
+
+type SerializeFn<E, I, const N: usize> =
+    for<'a> fn(&'a ErasedEntry<E, I, N>) -> &'a dyn erased_serde::Serialize;
+type DeserializeFn<E, S, I, const N: usize> = fn(
+    &mut dyn erased_serde::Deserializer<'_>,
+    &mut Map<E, S, I, N>,
+) -> Result<(), erased_serde::Error>;
+// Deserializes an entry written by an older schema version, capturing the conversion
+type MigrateFn<E, S, I, const N: usize> = Box<
+    dyn Fn(&mut dyn erased_serde::Deserializer<'_>, &mut Map<E, S, I, N>) -> Result<(), erased_serde::Error>
+        + Send
+        + Sync,
+>;
+
+struct Registration<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+{
+    tag: &'static str,
+    type_id: TypeId,
+    version: u32,
+    serialize: SerializeFn<E, I, N>,
+    deserialize: DeserializeFn<E, S, I, N>,
+    // By the version they migrate from
+    migrations: HashMap<u32, MigrateFn<E, S, I, N>>,
+}
+
+fn serialize_entry<A: 'static + ?Sized, E: 'static + ?Sized, I: ?Sized + RefAny, const N: usize>(
+    entry: &ErasedEntry<E, I, N>,
+) -> &dyn erased_serde::Serialize
+where
+    E: EntryFamily<A>,
+    EntryAt<E, A>: Serialize,
+{
+    match entry.hash_entry::<A>() {
+        Some(entry) => entry,
+        None => unreachable_internal_invariant("the registration is looked up by the argument type"),
+    }
+}
+
+fn deserialize_entry<
+    A: 'static + ?Sized,
+    E: 'static + ?Sized,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize,
+>(
+    deserializer: &mut dyn erased_serde::Deserializer<'_>,
+    map: &mut Map<E, S, I, N>,
+) -> Result<(), erased_serde::Error>
+where
+    E: EntryFamily<A>,
+    EntryAt<E, A>: DeserializeOwned,
+    I: CreateEntry<A, E>,
+{
+    let entry: EntryAt<E, A> = erased_serde::deserialize(deserializer)?;
+    let _ = map.insert::<A, _>(entry);
+    Ok(())
+}
+
+/// Associates argument types with stable tags, to (de)serialize a [`Map`] with matching parameters.
+///
+/// ```
+/// # use dependent_map::{Map, families::Singleton, registry::TypeRegistry};
+/// let mut registry = TypeRegistry::<Singleton>::new();
+/// registry.register::<u32>("count").register::<String>("name");
+///
+/// let mut map = Map::<Singleton>::new();
+/// let _ = map.insert(42u32);
+/// let json = serde_json::to_string(&registry.tagged(&map)).unwrap();
+/// assert_eq!(json, r#"{"count":[42]}"#);
+///
+/// let map = registry.deserialize_map(&mut serde_json::Deserializer::from_str(&json)).unwrap();
+/// assert_eq!(map.get_default::<u32>().map(|e| e.some), Some(42));
+/// ```
+pub struct TypeRegistry<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    // In the order of registration, which is also the order of serialization
+    registrations: Vec<Registration<E, S, I, N>>,
+    by_type: HashMap<TypeId, usize>,
+    by_tag: HashMap<&'static str, usize>,
+    unknown: UnknownTypes,
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    TypeRegistry<E, S, I, N>
+{
+    #[inline]
+    /// Create a new, empty, [`TypeRegistry`], failing on unregistered types.
+    pub fn new() -> Self {
+        Self {
+            registrations: Vec::new(),
+            by_type: HashMap::new(),
+            by_tag: HashMap::new(),
+            unknown: UnknownTypes::Error,
+        }
+    }
+    #[inline]
+    /// Set what to do with unregistered argument types and unknown tags.
+    pub fn with_unknown_types(mut self, unknown: UnknownTypes) -> Self {
+        self.unknown = unknown;
+        self
+    }
+    #[inline]
+    /// What is done with unregistered argument types and unknown tags.
+    pub fn unknown_types(&self) -> UnknownTypes {
+        self.unknown
+    }
+    #[inline]
+    /// The number of registered argument types.
+    pub fn len(&self) -> usize {
+        self.registrations.len()
+    }
+    #[inline]
+    /// Check if no argument type is registered.
+    pub fn is_empty(&self) -> bool {
+        self.registrations.is_empty()
+    }
+    #[inline]
+    /// The tags of all registered argument types, in the order they were registered.
+    pub fn tags(&self) -> impl '_ + Iterator<Item = &'static str> {
+        self.registrations.iter().map(|r| r.tag)
+    }
+    #[inline]
+    /// The tag the argument type `A` is registered under.
+    pub fn tag_of<A: 'static + ?Sized>(&self) -> Option<&'static str> {
+        match self.by_type.get(&TypeId::of::<A>()) {
+            Some(&index) => Some(self.registrations[index].tag),
+            None => None,
+        }
+    }
+    #[inline]
+    #[allow(clippy::manual_map)]
+    /// The current schema version of the argument type `A`.
+    pub fn version_of<A: 'static + ?Sized>(&self) -> Option<u32> {
+        match self.by_type.get(&TypeId::of::<A>()) {
+            Some(&index) => Some(self.registrations[index].version),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Pair a map with this registry, to serialize it.
+    pub fn tagged<'a>(&'a self, map: &'a Map<E, S, I, N>) -> Tagged<'a, E, S, I, N> {
+        Tagged {
+            registry: self,
+            map,
+        }
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    TypeRegistry<E, S, I, N>
+{
+    #[inline]
+    /// Register the argument type `A` under `tag`, with the schema version 0.
+    ///
+    /// The tag is written out in place of the type, so it should stay the same as long as
+    /// serialized maps are around, even if the type is renamed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `A` or `tag` is already registered.
+    pub fn register<A: 'static + ?Sized>(&mut self, tag: &'static str) -> &mut Self
+    where
+        E: EntryFamily<A>,
+        EntryAt<E, A>: Serialize + DeserializeOwned,
+        I: CreateEntry<A, E>,
+    {
+        self.register_version::<A>(tag, 0)
+    }
+    /// Register the argument type `A` under `tag`, with the given schema version.
+    ///
+    /// The version is written to snapshots along with the tag. Bump it whenever the
+    /// serialized form of the entries changes, and register a migration from the old version
+    /// with [`TypeRegistry::migrate`] to keep reading older snapshots.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `A` or `tag` is already registered.
+    pub fn register_version<A: 'static + ?Sized>(&mut self, tag: &'static str, version: u32) -> &mut Self
+    where
+        E: EntryFamily<A>,
+        EntryAt<E, A>: Serialize + DeserializeOwned,
+        I: CreateEntry<A, E>,
+    {
+        let type_id = TypeId::of::<A>();
+        assert!(
+            !self.by_type.contains_key(&type_id),
+            "{} is already registered",
+            core::any::type_name::<A>()
+        );
+        assert!(!self.by_tag.contains_key(tag), "the tag `{}` is already registered", tag);
+        let index = self.registrations.len();
+        self.registrations.push(Registration {
+            tag,
+            type_id,
+            version,
+            serialize: serialize_entry::<A, E, I, N>,
+            deserialize: deserialize_entry::<A, E, S, I, N>,
+            migrations: HashMap::new(),
+        });
+        let _ = self.by_type.insert(type_id, index);
+        let _ = self.by_tag.insert(tag, index);
+        self
+    }
+    /// Register a migration for entries of the argument type `A` written with the schema
+    /// version `from_version`.
+    ///
+    /// Such entries are deserialized as `Old`, the form they had back then, and converted
+    /// to the current entry type with `migrate`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `A` is not registered, if `from_version` is not older than its current
+    /// version, or if a migration from `from_version` is already registered.
+    pub fn migrate<A: 'static + ?Sized, Old: DeserializeOwned>(
+        &mut self,
+        from_version: u32,
+        migrate: impl 'static + Send + Sync + Fn(Old) -> EntryAt<E, A>,
+    ) -> &mut Self
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+    {
+        let name = core::any::type_name::<A>();
+        let registration = match self.by_type.get(&TypeId::of::<A>()) {
+            Some(&index) => &mut self.registrations[index],
+            None => panic!("{} is not registered", name),
+        };
+        assert!(
+            from_version < registration.version,
+            "can not migrate {} from version {}, its current version is {}",
+            name,
+            from_version,
+            registration.version
+        );
+        assert!(
+            !registration.migrations.contains_key(&from_version),
+            "a migration of {} from version {} is already registered",
+            name,
+            from_version
+        );
+        let migration: MigrateFn<E, S, I, N> = Box::new(move |deserializer, map| {
+            let old: Old = erased_serde::deserialize(deserializer)?;
+            let _ = map.insert::<A, _>(migrate(old));
+            Ok(())
+        });
+        let _ = registration.migrations.insert(from_version, migration);
+        self
+    }
+    /// Deserialize the entries of a tagged map into `map`, replacing entries with equal keys.
+    pub fn deserialize_into<'de, D: Deserializer<'de>>(
+        &self,
+        map: &mut Map<E, S, I, N>,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        deserializer.deserialize_map(MapSeed {
+            registry: self,
+            map,
+        })
+    }
+    /// Deserialize a tagged map into a new [`Map`].
+    pub fn deserialize_map<'de, D: Deserializer<'de>>(
+        &self,
+        deserializer: D,
+    ) -> Result<Map<E, S, I, N>, D::Error>
+    where
+        S: Default,
+    {
+        let mut map = Map::with_hasher(S::default());
+        self.deserialize_into(&mut map, deserializer)?;
+        Ok(map)
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> Default
+    for TypeRegistry<E, S, I, N>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> Debug
+    for TypeRegistry<E, S, I, N>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TypeRegistry")
+            .field(
+                "tags",
+                &self
+                    .registrations
+                    .iter()
+                    .map(|r| (r.tag, r.version))
+                    .collect::<Vec<_>>(),
+            )
+            .field("unknown", &self.unknown)
+            .finish()
+    }
+}
+
+/// A [`Map`] paired with a [`TypeRegistry`], serializable as a tagged map.
+///
+/// See [`TypeRegistry::tagged`].
+pub struct Tagged<
+    'a,
+    E: ?Sized,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    registry: &'a TypeRegistry<E, S, I, N>,
+    map: &'a Map<E, S, I, N>,
+}
+
+impl<'a, E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Serialize for Tagged<'a, E, S, I, N>
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let registry = self.registry;
+        // Count the entries of each type first, some formats need the lengths up front
+        let mut counts = vec![0usize; registry.registrations.len()];
+        for entry in self.map.iter() {
+            match registry.by_type.get(&entry.type_id()) {
+                Some(&index) => counts[index] += 1,
+                None if registry.unknown == UnknownTypes::Skip => {}
+                None => {
+                    return Err(ser::Error::custom(format_args!(
+                        "{} is not registered",
+                        entry.type_name()
+                    )))
+                }
+            }
+        }
+        let tags = counts.iter().filter(|&&count| count != 0).count();
+        let mut tagged = serializer.serialize_map(Some(tags))?;
+        for (registration, &len) in registry.registrations.iter().zip(&counts) {
+            if len != 0 {
+                tagged.serialize_entry(
+                    registration.tag,
+                    &EntriesOf {
+                        registration,
+                        map: self.map,
+                        len,
+                    },
+                )?;
+            }
+        }
+        tagged.end()
+    }
+}
+
+// The entries of a single argument type
+struct EntriesOf<'a, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+{
+    registration: &'a Registration<E, S, I, N>,
+    map: &'a Map<E, S, I, N>,
+    len: usize,
+}
+
+impl<'a, E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Serialize for EntriesOf<'a, E, S, I, N>
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        let type_id = self.registration.type_id;
+        for entry in self.map.iter().filter(|entry| entry.type_id() == type_id) {
+            seq.serialize_element((self.registration.serialize)(entry))?;
+        }
+        seq.end()
+    }
+}
+
+struct MapSeed<'a, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> {
+    registry: &'a TypeRegistry<E, S, I, N>,
+    map: &'a mut Map<E, S, I, N>,
+}
+
+impl<'a, 'de, E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Visitor<'de> for MapSeed<'a, E, S, I, N>
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str("a map from type tags to sequences of entries")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<(), M::Error> {
+        while let Some(tag) = access.next_key::<alloc::string::String>()? {
+            match self.registry.by_tag.get(tag.as_str()) {
+                Some(&index) => access.next_value_seed(EntriesSeed {
+                    registration: &self.registry.registrations[index],
+                    map: &mut *self.map,
+                })?,
+                None if self.registry.unknown == UnknownTypes::Skip => {
+                    let _ = access.next_value::<IgnoredAny>()?;
+                }
+                None => {
+                    return Err(de::Error::custom(format_args!("unknown type tag `{}`", tag)))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'de, E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    DeserializeSeed<'de> for MapSeed<'a, E, S, I, N>
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+// Inserts the entries of a single argument type
+struct EntriesSeed<'a, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+{
+    registration: &'a Registration<E, S, I, N>,
+    map: &'a mut Map<E, S, I, N>,
+}
+
+impl<'a, 'de, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Visitor<'de> for EntriesSeed<'a, E, S, I, N>
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "a sequence of entries for the tag `{}`", self.registration.tag)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq
+            .next_element_seed(EntrySeed {
+                registration: self.registration,
+                map: &mut *self.map,
+            })?
+            .is_some()
+        {}
+        Ok(())
+    }
+}
+
+impl<'a, 'de, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    DeserializeSeed<'de> for EntriesSeed<'a, E, S, I, N>
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+struct EntrySeed<'a, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> {
+    registration: &'a Registration<E, S, I, N>,
+    map: &'a mut Map<E, S, I, N>,
+}
+
+impl<'a, 'de, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    DeserializeSeed<'de> for EntrySeed<'a, E, S, I, N>
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let mut erased = <dyn erased_serde::Deserializer>::erase(deserializer);
+        match (self.registration.deserialize)(&mut erased, self.map) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(de::Error::custom(err)),
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Compact, versioned binary snapshots of a [`Map`], through a [`TypeRegistry`].
+//!
+//! Available with the `snapshot` feature. Entries are encoded with [`postcard`], and each
+//! argument type is written with its tag and the schema version it was registered with, see
+//! [`TypeRegistry::register_version`]. Snapshots of older versions are read with the
+//! migrations registered by [`TypeRegistry::migrate`].
+//!
+//! The layout of a snapshot is stable, all integers are little endian:
+//!
+//! - the magic bytes `DMAP`
+//! - the format version, a byte, currently [`FORMAT_VERSION`]
+//! - the length of the body, a `u32`
+//! - the body
+//! - the CRC-32 checksum of the body, a `u32`
+//!
+//! The body starts with the table of types, the number of types followed by the tag and
+//! schema version of each type. Then follows the number of entries, and for each entry the
+//! index of its type in the table, the length of its payload, and the payload itself. All
+//! numbers in the body are LEB128 varints.
+//!
+//! Reading a snapshot never panics on invalid input. The framing and checksum of a snapshot
+//! are validated before any entry is deserialized, and the error describes what is wrong.
+//!
+//! ```
+//! # use dependent_map::{Map, families::Singleton, registry::TypeRegistry};
+//! let mut registry = TypeRegistry::<Singleton>::new();
+//! registry.register::<u32>("count").register::<String>("name");
+//!
+//! let mut map = Map::<Singleton>::new();
+//! let _ = map.insert(42u32);
+//! let bytes = registry.write_snapshot(&map).unwrap();
+//!
+//! let map = registry.read_snapshot(&bytes).unwrap();
+//! assert_eq!(map.get_default::<u32>().map(|e| e.some), Some(42));
+//! ```
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::{Display, Formatter};
+use core::hash::BuildHasher;
+use crate::{HashableAny, Map};
+use super::{MigrateFn, Registration, TypeRegistry, UnknownTypes};
+
+/// The version of the snapshot layout written by [`TypeRegistry::write_snapshot`].
+pub const FORMAT_VERSION: u8 = 1;
+
+const MAGIC: [u8; 4] = *b"DMAP";
+// Magic, format version and body length
+const HEADER_LEN: usize = 4 + 1 + 4;
+const CHECKSUM_LEN: usize = 4;
+
+/// The error returned when writing or reading a snapshot fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The input does not start with the magic bytes of a snapshot.
+    BadMagic,
+    /// The snapshot has a layout version this crate can not read.
+    UnsupportedFormat {
+        /// The layout version of the snapshot.
+        version: u8,
+    },
+    /// The input ends before the end of the snapshot.
+    Truncated {
+        /// The length of the snapshot, as far as known.
+        expected: usize,
+        /// The length of the input.
+        actual: usize,
+    },
+    /// The input continues after the end of the snapshot.
+    TrailingBytes {
+        /// The number of bytes after the snapshot.
+        len: usize,
+    },
+    /// The body of the snapshot does not match its checksum, so it has been corrupted.
+    ChecksumMismatch {
+        /// The checksum stored in the snapshot.
+        expected: u32,
+        /// The checksum of the body.
+        actual: u32,
+    },
+    /// The body of the snapshot is not laid out as expected.
+    Malformed {
+        /// The position in the snapshot where the problem was found.
+        offset: usize,
+        /// What is wrong.
+        reason: &'static str,
+    },
+    /// The snapshot is too large to be written, its body exceeds 4 GiB.
+    TooLarge {
+        /// The length of the body.
+        len: usize,
+    },
+    /// The snapshot contains a tag which is not registered.
+    UnknownTag {
+        /// The unknown tag.
+        tag: String,
+    },
+    /// The map contains an argument type which is not registered.
+    Unregistered {
+        /// The name of the argument type.
+        type_name: &'static str,
+    },
+    /// The snapshot contains an older schema version of a type, without a migration from it.
+    MissingMigration {
+        /// The tag of the type.
+        tag: String,
+        /// The schema version in the snapshot.
+        version: u32,
+    },
+    /// The snapshot contains a newer schema version of a type than the one registered.
+    FutureVersion {
+        /// The tag of the type.
+        tag: String,
+        /// The schema version in the snapshot.
+        version: u32,
+        /// The schema version registered.
+        current: u32,
+    },
+    /// An entry could not be serialized or deserialized.
+    Entry {
+        /// The tag of the type of the entry.
+        tag: String,
+        /// The position of the entry in the snapshot.
+        index: usize,
+        /// The error of the (de)serializer.
+        message: String,
+    },
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SnapshotError::BadMagic => f.write_str("not a snapshot, the magic bytes are missing"),
+            SnapshotError::UnsupportedFormat { version } => {
+                write!(f, "unsupported snapshot format version {}", version)
+            }
+            SnapshotError::Truncated { expected, actual } => write!(
+                f,
+                "truncated snapshot, expected {} bytes but got {}",
+                expected, actual
+            ),
+            SnapshotError::TrailingBytes { len } => {
+                write!(f, "{} trailing bytes after the snapshot", len)
+            }
+            SnapshotError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "corrupt snapshot, expected checksum {:#010x} but got {:#010x}",
+                expected, actual
+            ),
+            SnapshotError::Malformed { offset, reason } => {
+                write!(f, "malformed snapshot at byte {}: {}", offset, reason)
+            }
+            SnapshotError::TooLarge { len } => {
+                write!(f, "snapshot body of {} bytes exceeds 4 GiB", len)
+            }
+            SnapshotError::UnknownTag { tag } => write!(f, "unknown type tag `{}`", tag),
+            SnapshotError::Unregistered { type_name } => {
+                write!(f, "{} is not registered", type_name)
+            }
+            SnapshotError::MissingMigration { tag, version } => write!(
+                f,
+                "no migration of `{}` from schema version {}",
+                tag, version
+            ),
+            SnapshotError::FutureVersion {
+                tag,
+                version,
+                current,
+            } => write!(
+                f,
+                "schema version {} of `{}` is newer than the registered version {}",
+                version, tag, current
+            ),
+            SnapshotError::Entry {
+                tag,
+                index,
+                message,
+            } => write!(f, "entry {} of type `{}`: {}", index, tag, message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SnapshotError {}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    TypeRegistry<E, S, I, N>
+{
+    /// Write a snapshot of `map`.
+    ///
+    /// Entries of unregistered argument types are left out with [`UnknownTypes::Skip`], and
+    /// fail with [`SnapshotError::Unregistered`] otherwise.
+    pub fn write_snapshot(&self, map: &Map<E, S, I, N>) -> Result<Vec<u8>, SnapshotError> {
+        // Only types with entries make it into the table, by the index of their registration
+        let mut table = vec![None; self.registrations.len()];
+        let mut types = Vec::new();
+        let mut entries = Vec::with_capacity(map.len());
+        for entry in map.iter() {
+            let index = match self.by_type.get(&entry.type_id()) {
+                Some(&index) => index,
+                None if self.unknown == UnknownTypes::Skip => continue,
+                None => {
+                    return Err(SnapshotError::Unregistered {
+                        type_name: entry.type_name(),
+                    })
+                }
+            };
+            let position = match table[index] {
+                Some(position) => position,
+                None => {
+                    let position = types.len();
+                    types.push(index);
+                    table[index] = Some(position);
+                    position
+                }
+            };
+            entries.push((position, entry));
+        }
+
+        let mut body = Vec::new();
+        write_varint(&mut body, types.len() as u64);
+        for &index in &types {
+            let registration = &self.registrations[index];
+            write_varint(&mut body, registration.tag.len() as u64);
+            body.extend_from_slice(registration.tag.as_bytes());
+            write_varint(&mut body, u64::from(registration.version));
+        }
+        write_varint(&mut body, entries.len() as u64);
+        let mut payload = Vec::new();
+        for (index, &(position, entry)) in entries.iter().enumerate() {
+            let registration = &self.registrations[types[position]];
+            payload.clear();
+            payload = match postcard::to_extend((registration.serialize)(entry), payload) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    return Err(SnapshotError::Entry {
+                        tag: registration.tag.to_string(),
+                        index,
+                        message: err.to_string(),
+                    })
+                }
+            };
+            write_varint(&mut body, position as u64);
+            write_varint(&mut body, payload.len() as u64);
+            body.extend_from_slice(&payload);
+        }
+
+        let len = match u32::try_from(body.len()) {
+            Ok(len) => len,
+            Err(_) => return Err(SnapshotError::TooLarge { len: body.len() }),
+        };
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len() + CHECKSUM_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        Ok(bytes)
+    }
+    /// Read a snapshot into a new [`Map`].
+    pub fn read_snapshot(&self, bytes: &[u8]) -> Result<Map<E, S, I, N>, SnapshotError>
+    where
+        S: Default,
+    {
+        let mut map = Map::with_hasher(S::default());
+        self.read_snapshot_into(&mut map, bytes)?;
+        Ok(map)
+    }
+    /// Read a snapshot into `map`, replacing entries with equal keys.
+    ///
+    /// Unknown tags are skipped with [`UnknownTypes::Skip`], and fail with
+    /// [`SnapshotError::UnknownTag`] otherwise. If an entry fails to deserialize, the entries
+    /// before it are left in the map, any other error leaves the map untouched.
+    pub fn read_snapshot_into(&self, map: &mut Map<E, S, I, N>, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let body = open(bytes)?;
+        let mut reader = Reader {
+            bytes: body,
+            pos: 0,
+        };
+
+        let count = reader.varint("the body ends before the number of types")?;
+        let mut types: Vec<(&str, Schema<'_, E, S, I, N>)> = Vec::new();
+        for _ in 0..count {
+            let len = reader.len("the body ends before the length of a tag")?;
+            let start = reader.offset();
+            let tag = match core::str::from_utf8(reader.take(len, "the body ends within a tag")?) {
+                Ok(tag) => tag,
+                Err(_) => return Err(malformed(start, "the tag is not valid UTF-8")),
+            };
+            let version = match u32::try_from(reader.varint("the body ends before a schema version")?) {
+                Ok(version) => version,
+                Err(_) => return Err(malformed(start, "the schema version overflows a u32")),
+            };
+            if types.iter().any(|&(other, _)| other == tag) {
+                return Err(malformed(start, "the tag is listed twice"));
+            }
+            let schema = self.schema(tag, version)?;
+            types.push((tag, schema));
+        }
+
+        // Frame all entries before deserializing any of them
+        let count = reader.varint("the body ends before the number of entries")?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let start = reader.offset();
+            let position = reader.len("the body ends before a type index")?;
+            if position >= types.len() {
+                return Err(malformed(start, "the type index is out of range"));
+            }
+            let len = reader.len("the body ends before the length of an entry")?;
+            entries.push((position, reader.take(len, "the body ends within an entry")?));
+        }
+        if reader.pos != body.len() {
+            return Err(malformed(reader.offset(), "unexpected bytes after the entries"));
+        }
+
+        for (index, (position, payload)) in entries.into_iter().enumerate() {
+            let (tag, ref schema) = types[position];
+            let mut deserializer = postcard::Deserializer::from_bytes(payload);
+            let result = {
+                let mut erased = <dyn erased_serde::Deserializer>::erase(&mut deserializer);
+                match *schema {
+                    Schema::Current(registration) => (registration.deserialize)(&mut erased, map),
+                    Schema::Migrate(migrate) => migrate(&mut erased, map),
+                    Schema::Skip => continue,
+                }
+            };
+            let message = match result {
+                Ok(()) => match deserializer.finalize() {
+                    Ok([]) => continue,
+                    Ok(rest) => format!("{} unread bytes", rest.len()),
+                    Err(err) => err.to_string(),
+                },
+                Err(err) => err.to_string(),
+            };
+            return Err(SnapshotError::Entry {
+                tag: tag.to_string(),
+                index,
+                message,
+            });
+        }
+        Ok(())
+    }
+    // How to read the entries of a type in a snapshot
+    fn schema(&self, tag: &str, version: u32) -> Result<Schema<'_, E, S, I, N>, SnapshotError> {
+        let registration = match self.by_tag.get(tag) {
+            Some(&index) => &self.registrations[index],
+            None if self.unknown == UnknownTypes::Skip => return Ok(Schema::Skip),
+            None => return Err(SnapshotError::UnknownTag { tag: tag.to_string() }),
+        };
+        if version == registration.version {
+            return Ok(Schema::Current(registration));
+        }
+        if version > registration.version {
+            return Err(SnapshotError::FutureVersion {
+                tag: tag.to_string(),
+                version,
+                current: registration.version,
+            });
+        }
+        match registration.migrations.get(&version) {
+            Some(migrate) => Ok(Schema::Migrate(migrate)),
+            None => Err(SnapshotError::MissingMigration {
+                tag: tag.to_string(),
+                version,
+            }),
+        }
+    }
+}
+
+enum Schema<'a, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> {
+    Current(&'a Registration<E, S, I, N>),
+    Migrate(&'a MigrateFn<E, S, I, N>),
+    Skip,
+}
+
+// Check the header and checksum, returning the body
+fn open(bytes: &[u8]) -> Result<&[u8], SnapshotError> {
+    let magic = &bytes[..bytes.len().min(MAGIC.len())];
+    if magic != &MAGIC[..magic.len()] {
+        return Err(SnapshotError::BadMagic);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(SnapshotError::Truncated {
+            expected: HEADER_LEN,
+            actual: bytes.len(),
+        });
+    }
+    if bytes[4] != FORMAT_VERSION {
+        return Err(SnapshotError::UnsupportedFormat { version: bytes[4] });
+    }
+    let len = u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
+    let expected = HEADER_LEN.saturating_add(len).saturating_add(CHECKSUM_LEN);
+    if bytes.len() < expected {
+        return Err(SnapshotError::Truncated {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+    if bytes.len() > expected {
+        return Err(SnapshotError::TrailingBytes {
+            len: bytes.len() - expected,
+        });
+    }
+    let body = &bytes[HEADER_LEN..HEADER_LEN + len];
+    let checksum = &bytes[HEADER_LEN + len..];
+    let expected = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    let actual = crc32(body);
+    if expected != actual {
+        return Err(SnapshotError::ChecksumMismatch { expected, actual });
+    }
+    Ok(body)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn malformed(offset: usize, reason: &'static str) -> SnapshotError {
+    SnapshotError::Malformed { offset, reason }
+}
+
+// Reads the body of a snapshot, reporting offsets into the whole snapshot
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn offset(&self) -> usize {
+        HEADER_LEN + self.pos
+    }
+    fn varint(&mut self, missing: &'static str) -> Result<u64, SnapshotError> {
+        let start = self.offset();
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = match self.bytes.get(self.pos) {
+                Some(&byte) => byte,
+                None => return Err(malformed(start, missing)),
+            };
+            self.pos += 1;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(malformed(start, "a varint overflows a u64"));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(malformed(start, "a varint overflows a u64"))
+    }
+    fn len(&mut self, missing: &'static str) -> Result<usize, SnapshotError> {
+        let start = self.offset();
+        match usize::try_from(self.varint(missing)?) {
+            Ok(len) => Ok(len),
+            Err(_) => Err(malformed(start, "a length overflows a usize")),
+        }
+    }
+    fn take(&mut self, len: usize, missing: &'static str) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() - self.pos < len {
+            return Err(malformed(self.offset(), missing));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+// The CRC-32 used by zlib, PNG and others
+fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    0xedb8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,317 @@
+//! Storage for the type-erased entries of a map, keeping small entries inline.
+//!
+//! Changes to this module should also be tested under Miri, with `cargo +nightly miri test`.
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ptr::NonNull;
+use alloc::boxed::Box;
+use crate::{CreateEntry, EntryAt, EntryFamily, InnerEntry};
+use crate::map::unreachable_internal_invariant;
+
+// Recovers the (possibly fat) pointer to the storage type from the address of an inline entry.
+// Inline entries move together with the table slot they are stored in, so unlike for a `Box`,
+// a pointer to them can not be stored once and has to be coerced anew on every access.
+type Coerce<I> = fn(*mut u8) -> *mut I;
+
+enum Repr<I: ?Sized, const N: usize> {
+    Inline {
+        buf: InlineBuf<N>,
+        coerce: Coerce<I>,
+    },
+    Boxed(Box<I>),
+    // Owned like a box, but the memory belongs to an arena that outlives the storage
+    #[cfg(feature = "bumpalo")]
+    Arena {
+        ptr: ArenaPtr,
+        coerce: Coerce<I>,
+    },
+}
+
+// The buffer of an inline entry. Entries may have interior mutability, so all pointers into the
+// buffer are taken from the `UnsafeCell`, even when only a shared reference to it is held.
+struct InlineBuf<const N: usize>(UnsafeCell<[MaybeUninit<usize>; N]>);
+
+impl<const N: usize> InlineBuf<N> {
+    #[inline]
+    fn new() -> Self {
+        Self(UnsafeCell::new([MaybeUninit::uninit(); N]))
+    }
+    #[inline]
+    fn ptr(&self) -> *mut u8 {
+        self.0.get().cast()
+    }
+}
+
+// Auto traits follow the stored entry through the marker in `Storage`
+unsafe impl<const N: usize> Sync for InlineBuf<N> {}
+
+// The address of an entry allocated in an arena, uniquely owned just like an inline entry.
+#[cfg(feature = "bumpalo")]
+struct ArenaPtr(NonNull<u8>);
+
+// Auto traits follow the stored entry through the marker in `Storage`
+#[cfg(feature = "bumpalo")]
+unsafe impl Send for ArenaPtr {}
+#[cfg(feature = "bumpalo")]
+unsafe impl Sync for ArenaPtr {}
+
+/// Owns a single entry, either inline in up to `N` words, or boxed up on the heap.
+pub(crate) struct Storage<I: ?Sized, const N: usize> {
+    repr: Repr<I, N>,
+    // The inline buffer is plain data, make sure auto traits still follow the stored entry
+    _marker: PhantomData<Box<I>>,
+}
+
+fn coerce_entry<A: 'static + ?Sized, E: 'static + ?Sized + EntryFamily<A>, I: ?Sized>(
+    ptr: *mut u8,
+) -> *mut I
+where
+    I: CreateEntry<A, E>,
+{
+    match I::coerce_entry(ptr.cast::<InnerEntry<E, A>>()) {
+        Some(ptr) => ptr,
+        None => unreachable_internal_invariant("Only coercible entries are stored unboxed"),
+    }
+}
+
+// Implementations of `CreateEntry` written before `coerce_entry` existed don't provide it,
+// their entries are always boxed up.
+pub(crate) fn can_coerce<A: 'static + ?Sized, E: 'static + ?Sized + EntryFamily<A>, I: ?Sized>() -> bool
+where
+    I: CreateEntry<A, E>,
+{
+    // the pointer is only coerced, never dereferenced
+    I::coerce_entry(NonNull::<InnerEntry<E, A>>::dangling().as_ptr()).is_some()
+}
+
+impl<I: ?Sized, const N: usize> Storage<I, N> {
+    /// Check if a value of type `T` is stored inline.
+    #[inline]
+    pub(crate) const fn fits_inline<T>() -> bool {
+        core::mem::size_of::<T>() <= N * core::mem::size_of::<usize>()
+            && core::mem::align_of::<T>() <= core::mem::align_of::<usize>()
+    }
+
+    #[inline]
+    pub(crate) fn new<A: 'static + ?Sized, E: 'static + ?Sized + EntryFamily<A>>(
+        entry: EntryAt<E, A>,
+    ) -> Self
+    where
+        I: CreateEntry<A, E>,
+    {
+        let repr = if Self::fits_inline::<InnerEntry<E, A>>() && can_coerce::<A, E, I>() {
+            let buf = InlineBuf::new();
+            // checked size and alignment above
+            unsafe {
+                buf.ptr()
+                    .cast::<InnerEntry<E, A>>()
+                    .write(InnerEntry::new(entry))
+            };
+            Repr::Inline {
+                buf,
+                coerce: coerce_entry::<A, E, I>,
+            }
+        } else {
+            Repr::Boxed(I::from_entry(entry))
+        };
+        Self {
+            repr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Store an entry inline, or in the arena if it doesn't fit.
+    /// Entries that can't be coerced are boxed up instead.
+    ///
+    /// # Safety
+    ///
+    /// The arena must not be reset or dropped before the storage is dropped.
+    #[cfg(feature = "bumpalo")]
+    #[inline]
+    pub(crate) unsafe fn new_in<A: 'static + ?Sized, E: 'static + ?Sized + EntryFamily<A>>(
+        entry: EntryAt<E, A>,
+        arena: &bumpalo::Bump,
+    ) -> Self
+    where
+        I: CreateEntry<A, E>,
+    {
+        if Self::fits_inline::<InnerEntry<E, A>>() || !can_coerce::<A, E, I>() {
+            return Self::new::<A, E>(entry);
+        }
+        let ptr = NonNull::from(arena.alloc(InnerEntry::<E, A>::new(entry)));
+        Self {
+            repr: Repr::Arena {
+                ptr: ArenaPtr(ptr.cast()),
+                coerce: coerce_entry::<A, E, I>,
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self) -> &I {
+        match &self.repr {
+            // the buffer contains an initialized entry of the type `coerce` was created for
+            Repr::Inline { buf, coerce } => unsafe { &*coerce(buf.ptr()) },
+            Repr::Boxed(boxed) => boxed,
+            // the arena outlives the storage
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { ptr, coerce } => unsafe { &*coerce(ptr.0.as_ptr()) },
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_mut(&mut self) -> &mut I {
+        match &mut self.repr {
+            // the buffer contains an initialized entry of the type `coerce` was created for
+            Repr::Inline { buf, coerce } => unsafe { &mut *coerce(buf.ptr()) },
+            Repr::Boxed(boxed) => boxed,
+            // the arena outlives the storage
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { ptr, coerce } => unsafe { &mut *coerce(ptr.0.as_ptr()) },
+        }
+    }
+
+    /// Get the stored entry, without going through its vtable.
+    ///
+    /// # Safety
+    ///
+    /// The stored entry must be of type `T`.
+    #[inline]
+    pub(crate) unsafe fn get_unchecked<T>(&self) -> &T {
+        match &self.repr {
+            Repr::Inline { buf, .. } => &*buf.ptr().cast::<T>(),
+            Repr::Boxed(boxed) => &*(&**boxed as *const I as *const T),
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { ptr, .. } => &*ptr.0.as_ptr().cast::<T>(),
+        }
+    }
+
+    /// Get the stored entry mutably, without going through its vtable.
+    ///
+    /// # Safety
+    ///
+    /// The stored entry must be of type `T`.
+    #[inline]
+    pub(crate) unsafe fn get_unchecked_mut<T>(&mut self) -> &mut T {
+        match &mut self.repr {
+            Repr::Inline { buf, .. } => &mut *buf.ptr().cast::<T>(),
+            Repr::Boxed(boxed) => &mut *(&mut **boxed as *mut I as *mut T),
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { ptr, .. } => &mut *ptr.0.as_ptr().cast::<T>(),
+        }
+    }
+
+    /// Move the stored entry out.
+    ///
+    /// # Safety
+    ///
+    /// The stored entry must be of type `T`.
+    #[inline]
+    pub(crate) unsafe fn take<T>(self) -> T {
+        match ManuallyDrop::new(self).repr {
+            Repr::Inline { ref buf, .. } => buf.ptr().cast::<T>().read(),
+            // the box is not dropped, move it out to free the entry through its own pointer
+            Repr::Boxed(ref boxed) => {
+                let boxed = core::ptr::read(boxed);
+                *Box::from_raw(Box::into_raw(boxed) as *mut T)
+            }
+            // the memory is left to the arena
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { ref ptr, .. } => ptr.0.as_ptr().cast::<T>().read(),
+        }
+    }
+
+    /// Move the stored entry to the heap, if it is not already.
+    pub(crate) fn into_box(self) -> Box<I> {
+        let this = ManuallyDrop::new(self);
+        let (src, coerce) = match &this.repr {
+            Repr::Inline { buf, coerce } => (buf.ptr(), *coerce),
+            // `this` is not dropped, take ownership of the box
+            Repr::Boxed(boxed) => return unsafe { core::ptr::read(boxed) },
+            // the memory is left to the arena
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { ptr, coerce } => (ptr.0.as_ptr(), *coerce),
+        };
+        // `src` points to an initialized entry of the type `coerce` was created for
+        let layout = Layout::for_value(unsafe { &*coerce(src) });
+        let dst = if layout.size() == 0 {
+            // dangling, but well aligned, as expected for zero-sized boxes
+            layout.align() as *mut u8
+        } else {
+            let dst = unsafe { alloc::alloc::alloc(layout) };
+            if dst.is_null() {
+                alloc::alloc::handle_alloc_error(layout);
+            }
+            dst
+        };
+        // moving the entry, `this` is not dropped
+        unsafe {
+            core::ptr::copy_nonoverlapping(src, dst, layout.size());
+            Box::from_raw(coerce(dst))
+        }
+    }
+
+    /// Store a boxed entry in the same way as `self`, moving it inline if `self` is.
+    /// Entries stored in an arena stay boxed, the arena is not reachable from here.
+    ///
+    /// The boxed entry must be of the same type as the entry stored in `self`.
+    fn like(&self, boxed: Box<I>) -> Self {
+        let repr = match &self.repr {
+            Repr::Inline { coerce, .. } => {
+                let buf = InlineBuf::new();
+                let layout = Layout::for_value(&*boxed);
+                let src = Box::into_raw(boxed);
+                // Same type as the inline entry, so it fits. Moving the entry, and then freeing
+                // the allocation without dropping it.
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        src as *const u8,
+                        buf.ptr(),
+                        layout.size(),
+                    );
+                    if layout.size() != 0 {
+                        alloc::alloc::dealloc(src as *mut u8, layout);
+                    }
+                }
+                Repr::Inline {
+                    buf,
+                    coerce: *coerce,
+                }
+            }
+            Repr::Boxed(_) => Repr::Boxed(boxed),
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { .. } => Repr::Boxed(boxed),
+        };
+        Self {
+            repr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<I: ?Sized, const N: usize> Drop for Storage<I, N> {
+    #[inline]
+    fn drop(&mut self) {
+        match &mut self.repr {
+            // the buffer contains an initialized entry of the type `coerce` was created for
+            Repr::Inline { buf, coerce } => unsafe {
+                core::ptr::drop_in_place(coerce(buf.ptr()))
+            },
+            Repr::Boxed(_) => {}
+            // only the entry is dropped, its memory is freed with the arena
+            #[cfg(feature = "bumpalo")]
+            Repr::Arena { ptr, coerce } => unsafe { core::ptr::drop_in_place(coerce(ptr.0.as_ptr())) },
+        }
+    }
+}
+
+impl<I: ?Sized + dyn_clone::DynClone, const N: usize> Clone for Storage<I, N> {
+    #[inline]
+    fn clone(&self) -> Self {
+        // `DynClone` can only clone into a box. Inline entries are moved back out of it.
+        self.like(dyn_clone::clone_box(self.get()))
+    }
+}
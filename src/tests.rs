@@ -3,15 +3,30 @@ use crate::*;
 use crate::families::*;
 use crate::variants::*;
 
-#[derive(Clone, Debug, PartialEq)] struct A(i32);
-#[derive(Clone, Debug, PartialEq)] struct B(i32);
-#[derive(Clone, Debug, PartialEq)] struct C(i32);
-#[derive(Clone, Debug, PartialEq)] struct D(i32);
-#[derive(Clone, Debug, PartialEq)] struct E(i32);
-#[derive(Clone, Debug, PartialEq)] struct F(i32);
-#[derive(Clone, Debug, PartialEq)] struct J(i32);
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct A(i32);
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct B(i32);
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct C(i32);
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct D(i32);
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct E(i32);
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct F(i32);
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct J(i32);
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn test_some() {
     let mut map = Map::<Singleton>::new();
     let _ = map.insert(42u32);
@@ -28,7 +43,8 @@ impl<A> EntryFamily<A> for MultiValued {
     type Result = Multiple<A>;
 }
 
-#[derive(Debug, PartialOrd, Ord, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Multiple<A> { pub value: A, pub variant: u32 }
 impl<A> HashEntry for Multiple<A> {
     type Key = u32;
@@ -41,6 +57,14 @@ impl<A> HashEntry for Multiple<A> {
     fn split_mut(&mut self) -> (&u32, &mut A) {
         (&self.variant, &mut self.value)
     }
+    #[inline]
+    fn into_parts(self) -> (u32, A) {
+        (self.variant, self.value)
+    }
+    #[inline]
+    fn from_parts(variant: u32, value: A) -> Self {
+        Multiple { variant, value }
+    }
 }
 impl<A> From<(u32, A)> for Multiple<A> {
     #[inline]
@@ -124,6 +148,43 @@ fn test_varieties() {
         let _ = map.insert(A(1));
     }
     assert_clone::<CloneableMap<Singleton>>();
+    assert_send::<SendMap<Singleton>>();
+    assert_sync::<SyncMap<Singleton>>();
+    assert_send::<SendSyncMap<Singleton>>();
+    assert_sync::<SendSyncMap<Singleton>>();
+    assert_clone::<CloneableSendSyncMap<Singleton>>();
+    assert_debug::<DebuggableSendSyncMap<Singleton>>();
+    {
+        let mut map: SendSyncMap<MultiValued> = Default::default();
+        let _ = map.insert((0, A(42)));
+        let _ = map.insert((1, B(1337)));
+        let map = std::thread::spawn(move || {
+            assert_eq!(map.get::<A, _>(&0).map(|e| &e.value), Some(&A(42)));
+            map
+        })
+        .join()
+        .unwrap();
+        assert_eq!(map.len(), 2);
+    }
+    {
+        let mut map: SyncMap<Singleton> = Default::default();
+        let _ = map.insert(A(1));
+        std::thread::scope(|s| {
+            let _ = s.spawn(|| assert_eq!(map.get_default::<A>().map(|e| &e.some), Some(&A(1))));
+        });
+    }
+    {
+        let mut map: CloneableSendMap<Singleton> = Default::default();
+        let _ = map.insert(A(1));
+        let copy = map.clone();
+        assert_eq!(copy.get_default::<A>().map(|e| &e.some), Some(&A(1)));
+        let mut map: ComparableSendSyncMap<Singleton> = Default::default();
+        let _ = map.insert(A(1));
+        assert!(map == map);
+        let mut map: DebuggableSendMap<Singleton> = Default::default();
+        let _ = map.insert(A(1));
+        assert!(format!("{:?}", map).contains("A(1)"));
+    }
     {
         let mut map: DebuggableMap<MultiValued> = Default::default();
         let _ = map.insert((0, A(42)));
@@ -132,3 +193,1029 @@ fn test_varieties() {
     }
     assert_debug::<DebuggableMap<Singleton>>();
 }
+
+#[test]
+fn test_entry_combinators() {
+    let mut map = Map::<Singleton>::new();
+    *map.entry::<u32>(()).or_insert(1) += 1;
+    *map.entry::<u32>(()).or_insert(10) += 1;
+    assert_eq!(**map.get_default::<u32>().expect(""), 3);
+
+    let _ = map.entry::<u64>(()).and_modify(|v| *v += 1).or_default();
+    assert_eq!(**map.get_default::<u64>().expect(""), 0);
+    let _ = map.entry::<u64>(()).and_modify(|v| *v += 1).or_default();
+    assert_eq!(**map.get_default::<u64>().expect(""), 1);
+
+    let entry = map.entry::<A>(()).or_insert_entry(A(5));
+    assert_eq!(entry.get(), &A(5));
+
+    let mut map = Map::<MultiValued>::new();
+    let _ = map.entry::<u32>(7).or_insert_with_key(|k| k * 2);
+    let _ = map.entry::<u32>(7).or_insert_with(|| unreachable!());
+    assert_eq!(map.get::<u32, _>(&7).expect("").value, 14);
+    assert_eq!(map.entry::<u32>(8).key(), &8);
+}
+
+#[test]
+fn test_default_key() {
+    let mut map = Map::<Singleton>::new();
+    assert!(!map.contains_default::<u32>());
+    *map.entry_default::<u32>().or_insert(1) += 1;
+    assert!(map.contains_default::<u32>());
+    *map.get_default_mut::<u32>().expect("") += 1;
+    assert_eq!(**map.get_default::<u32>().expect(""), 3);
+    assert_eq!(map.remove_default::<u32>(), std::option::Option::Some(Some { some: 3 }));
+    assert_eq!(map.remove_default::<u32>(), None);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_erased_iteration() {
+    let mut map = Map::<Singleton>::new();
+    let _ = map.insert(A(1));
+    let _ = map.insert(B(2));
+
+    let mut names: Vec<_> = map.iter().map(|e| e.type_name()).collect();
+    names.sort_unstable();
+    assert_eq!(names, [std::any::type_name::<A>(), std::any::type_name::<B>()]);
+    assert!(map.iter().any(|e| e.type_id() == std::any::TypeId::of::<A>()));
+
+    for mut entry in &mut map {
+        if let std::option::Option::Some(b) = entry.get_mut::<B>() {
+            b.0 += 40;
+        }
+    }
+    assert_eq!(**map.get_default::<B>().expect(""), B(42));
+
+    let mut found_a = false;
+    for entry in map {
+        match entry.into_hash_entry::<A>() {
+            Ok(a) => found_a = a.some == A(1),
+            Err(other) => assert!(other.is::<B>()),
+        }
+    }
+    assert!(found_a);
+}
+
+#[test]
+fn test_erased_entry_mut() {
+    let mut map = Map::<Singleton>::new();
+    let _ = map.insert(1u32);
+    let _ = map.insert(2u64);
+
+    // Values can be modified, but the entries stay where the map expects them
+    for entry in map.iter_mut() {
+        match entry.into_mut::<u32>() {
+            Ok(value) => *value += 10,
+            Err(other) => assert!(other.is::<u64>()),
+        }
+    }
+    assert_eq!(map.len(), 2);
+    assert_eq!(**map.get_default::<u32>().expect(""), 11);
+    assert_eq!(**map.get_default::<u64>().expect(""), 2);
+
+    let mut map = Map::<MultiValued>::new();
+    let _ = map.insert((1, A(1)));
+    for mut entry in &mut map {
+        assert_eq!(entry.reborrow().get_mut::<B>(), None);
+        let (key, value) = entry.into_split_mut::<A>().ok().expect("");
+        value.0 += *key as i32;
+    }
+    assert_eq!(map.get::<A, _>(&1).map(|e| &e.value), std::option::Option::Some(&A(2)));
+}
+
+#[test]
+fn test_typed_iteration() {
+    let mut map = Map::<MultiValued>::new();
+    let _ = map.insert((0, 42u32));
+    let _ = map.insert((1, 1337u32));
+    let _ = map.insert((0, A(7)));
+
+    assert_eq!(map.count_of::<u32>(), 2);
+    assert_eq!(map.count_of::<A>(), 1);
+    assert_eq!(map.count_of::<B>(), 0);
+
+    let mut keys: Vec<_> = map.keys_of::<u32>().copied().collect();
+    keys.sort_unstable();
+    assert_eq!(keys, [0, 1]);
+
+    for (key, value) in map.iter_of_mut::<u32>() {
+        *value += key;
+    }
+    let mut values: Vec<_> = map.values_of::<u32>().copied().collect();
+    values.sort_unstable();
+    assert_eq!(values, [42, 1338]);
+    assert!(map.iter_of::<A>().all(|e| e.value == A(7)));
+}
+
+#[test]
+fn test_remove() {
+    let mut map = Map::<MultiValued>::new();
+    let _ = map.insert((0, 42u32));
+    let _ = map.insert((1, 1337u32));
+    assert_eq!(map.remove::<u32, _>(&0), std::option::Option::Some(42));
+    assert_eq!(map.remove::<u32, _>(&0), None);
+
+    match map.entry::<u32>(1) {
+        Entry::Occupied(e) => assert_eq!(e.remove_entry().into_parts(), (1, 1337)),
+        Entry::Vacant(_) => panic!("entry should be occupied"),
+    }
+    assert!(map.is_empty());
+
+    let mut map = Map::<Singleton>::new();
+    let _ = map.insert(A(1));
+    match map.entry_default::<A>() {
+        Entry::Occupied(e) => assert_eq!(e.remove(), A(1)),
+        Entry::Vacant(_) => panic!("entry should be occupied"),
+    }
+    assert_eq!(map.remove::<A, _>(&()), None);
+}
+
+#[test]
+fn test_retain_and_drain() {
+    let mut map = Map::<MultiValued>::new();
+    for i in 0..10 {
+        let _ = map.insert((i, i));
+        let _ = map.insert((i, A(i as i32)));
+    }
+    map.retain_of::<u32>(|k, _| k % 2 == 0);
+    assert_eq!(map.count_of::<u32>(), 5);
+    assert_eq!(map.count_of::<A>(), 10);
+
+    map.retain(|e| !e.is::<A>() || e.hash_entry::<A>().expect("").variant < 5);
+    assert_eq!(map.count_of::<A>(), 5);
+
+    let mut drained: Vec<_> = map.drain_of::<u32>().map(|e| e.value).collect();
+    drained.sort_unstable();
+    assert_eq!(drained, [0, 2, 4, 6, 8]);
+    assert_eq!(map.len(), 5);
+
+    let extracted = map.extract_if(|e| e.hash_entry::<A>().expect("").variant == 0).count();
+    assert_eq!(extracted, 1);
+    assert_eq!(map.drain().count(), 4);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_remove_type() {
+    let mut map = Map::<MultiValued>::new();
+    for i in 0..4 {
+        let _ = map.insert((i, A(i as i32)));
+        let _ = map.insert((i, B(i as i32)));
+    }
+    let _ = map.insert((0, C(0)));
+    assert!(map.contains_type::<A>());
+    assert_eq!(map.remove_type::<A>(), 4);
+    assert!(!map.contains_type::<A>());
+    assert_eq!(map.remove_type::<A>(), 0);
+
+    let mut taken: Vec<_> = map.take_type::<B>().into_iter().map(|e| e.variant).collect();
+    taken.sort_unstable();
+    assert_eq!(taken, [0, 1, 2, 3]);
+    assert_eq!(map.len(), 1);
+    assert!(map.contains_type::<C>());
+}
+
+#[test]
+fn test_with_hasher() {
+    use std::hash::BuildHasherDefault;
+    use std::collections::hash_map::DefaultHasher as StdHasher;
+    type Seeded = BuildHasherDefault<StdHasher>;
+
+    let mut map = ComparableMap::<Singleton, Seeded>::with_hasher(Seeded::default());
+    let mut map2 = ComparableMap::<Singleton, Seeded>::with_capacity_and_hasher(4, Seeded::default());
+    assert!(map2.capacity() >= 4);
+    let _ = map.insert(A(1));
+    let _ = map2.insert(A(1));
+    assert!(map == map2);
+    let _: &Seeded = map.hasher();
+}
+
+#[test]
+fn test_rehash_with() {
+    use std::hash::BuildHasherDefault;
+    use std::collections::hash_map::{DefaultHasher as StdHasher, RandomState};
+    type Seeded = BuildHasherDefault<StdHasher>;
+    type Storage<S> = dyn PartialEqHashableAny<<S as std::hash::BuildHasher>::Hasher>;
+
+    fn fill<S: std::hash::BuildHasher>(map: &mut ComparableMap<MultiValued, S>)
+    where
+        S::Hasher: 'static,
+        Storage<S>: CreateEntry<A, MultiValued>,
+    {
+        for i in 0..20 {
+            let _ = map.insert((i, A(i as i32)));
+        }
+    }
+    // Differently seeded hash builders with the same type of hasher share the storage type
+    let mut map = ComparableMap::<MultiValued, RandomState>::new();
+    let mut other = ComparableMap::<MultiValued, RandomState>::new();
+    fill(&mut map);
+    fill(&mut other);
+    let rehashed: ComparableMap<MultiValued, Seeded> = other.rehash_with(Seeded::default());
+    assert!(map == rehashed);
+    assert!(rehashed == map);
+
+    // Moving to another type of hasher re-creates the entries in the new storage type
+    let mut fast = ComparableMap::<MultiValued>::new();
+    fill(&mut fast);
+    let _ = fast.insert((0, B(0)));
+    let mut to_std = StorageConversion::<MultiValued, _, Storage<Seeded>>::new();
+    let _ = to_std.register::<A>();
+    let fast = match fast.rehash_into(Seeded::default(), &to_std) {
+        Ok(_) => panic!("B is not registered"),
+        Err(err) => {
+            assert_eq!(err.type_name, core::any::type_name::<B>());
+            err.map
+        }
+    };
+    assert_eq!(fast.len(), 21);
+    let _ = to_std.register::<B>();
+    let deterministic = fast.rehash_into(Seeded::default(), &to_std).expect("");
+    assert_eq!(deterministic.len(), 21);
+    assert_eq!(deterministic.get::<A, _>(&7).expect("").value, A(7));
+    assert_eq!(deterministic.get::<B, _>(&0).expect("").value, B(0));
+
+    let mut to_fast = StorageConversion::<MultiValued, _, Storage<DefaultHashBuilder>>::new();
+    let _ = to_fast.register::<A>().register::<B>();
+    let fast: ComparableMap<MultiValued> = deterministic.rehash_into(DefaultHashBuilder::default(), &to_fast).expect("");
+    assert_eq!(fast.get::<A, _>(&19).expect("").value, A(19));
+    assert!(fast.contains_key::<B, _>(&0));
+}
+
+#[test]
+fn test_try_insert() {
+    let mut map = Map::<Singleton>::new();
+    assert_eq!(map.try_reserve(usize::MAX), Err(TryReserveError::CapacityOverflow));
+    assert_eq!(map.try_reserve(10), Ok(()));
+    assert!(map.capacity() >= 10);
+
+    *map.try_insert(A(1)).expect("vacant") = A(2);
+    match map.try_insert(A(3)) {
+        Ok(_) => panic!("entry should be occupied"),
+        Err(err) => {
+            assert_eq!(err.entry.get(), &A(2));
+            assert_eq!(err.value.some, A(3));
+            assert_eq!(err.to_string(), "failed to insert A(3), key () already exists with value A(2)");
+        }
+    }
+    assert_eq!(**map.get_default::<A>().expect(""), A(2));
+}
+
+#[test]
+fn test_get_many_mut() {
+    let mut map = Map::<Singleton>::new();
+    let _ = map.insert(A(1));
+    let _ = map.insert(B(2));
+    let _ = map.insert(C(3));
+    {
+        let (a, b, c) = map.get_many_mut::<(A, B, C)>((&(), &(), &())).expect("all present");
+        std::mem::swap(&mut a.0, &mut c.0);
+        b.0 += 1;
+    }
+    assert_eq!(**map.get_default::<A>().expect(""), A(3));
+    assert_eq!(**map.get_default::<B>().expect(""), B(3));
+    assert!(map.get_many_mut::<(A, D)>((&(), &())).is_none());
+    assert!(map.get_many_mut::<(A, A)>((&(), &())).is_none());
+
+    let mut map = Map::<MultiValued>::new();
+    let _ = map.insert((0, 10u32));
+    let _ = map.insert((1, 20u32));
+    {
+        let [x, y] = map.get_many_key_mut::<u32, _, 2>([&0, &1]).expect("all present");
+        std::mem::swap(x, y);
+    }
+    assert_eq!(map.get::<u32, _>(&0).expect("").value, 20);
+    assert!(map.get_many_key_mut::<u32, _, 2>([&0, &0]).is_none());
+    assert!(map.get_many_key_mut::<u32, _, 2>([&0, &2]).is_none());
+}
+
+#[test]
+fn test_cell_map() {
+    let mut map = CellMap::<Singleton>::new();
+    let _ = map.insert(A(1));
+    let _ = map.insert(B(2));
+    {
+        let a = map.borrow::<A>();
+        let mut b = map.borrow_mut::<B>();
+        b.0 += a.0;
+        assert_eq!(map.try_borrow::<A>().map(|a| a.0), Ok(1));
+        assert_eq!(map.try_borrow::<B>().err(), std::option::Option::Some(BorrowError::Conflict(std::any::type_name::<B>())));
+        assert!(map.try_borrow_mut::<A>().is_err());
+        assert_eq!(map.try_borrow::<C>().err(), std::option::Option::Some(BorrowError::Missing(std::any::type_name::<C>())));
+    }
+    assert_eq!(*map.borrow::<B>(), B(3));
+    map.get_mut::<A, _>(&()).expect("").0 = 10;
+    assert_eq!(map.remove::<A, _>(&()), std::option::Option::Some(A(10)));
+    assert_eq!(map.len(), 1);
+
+    type CloneDebug = dyn CloneDebugHashableAny<DefaultHasher>;
+    trait CloneDebugHashableAny<H: std::hash::Hasher>: CloneableHashableAny<H> + DebugEntry {}
+    impl<H: std::hash::Hasher, T: CloneableHashableAny<H> + DebugEntry> CloneDebugHashableAny<H> for T {}
+    crate::create_entry_impl!(CloneDebugHashableAny<H> where EntryAt<E, A>: Clone, KeyAt<E, A>: Debug, ValueAt<E, A>: Debug,);
+
+    let mut map = CellMap::<MultiValued, DefaultHashBuilder, CloneDebug>::new();
+    let _ = map.insert((1, A(1)));
+    let map2 = map.clone();
+    *map.borrow_key_mut::<A, _>(&1) = A(2);
+    assert_eq!(*map2.borrow_key::<A, _>(&1), A(1));
+    assert!(format!("{:?}", map2).ends_with("key: 1 }: RefCell { value: A(1) }}"));
+}
+
+#[test]
+#[cfg(all(feature = "std", not(loom)))]
+fn test_concurrent_map() {
+    use crate::variants::concurrent::Entry as ConcurrentEntry;
+
+    let map = ConcurrentMap::<MultiValued>::with_shard_amount(4);
+    assert_eq!(map.shard_amount(), 4);
+    assert!(map.insert((0, A(1))).is_none());
+    assert!(map.insert((1, A(2))).is_none());
+    assert_eq!(map.insert((0, B(3))), None);
+    assert_eq!(map.get::<A, _>(&0).map(|e| e.value.0), std::option::Option::Some(1));
+    assert_eq!(map.get::<A, _>(&1).expect("inserted").key(), &1);
+    assert!(map.get::<B, _>(&1).is_none());
+    map.get_mut::<A, _>(&1).expect("inserted").0 = 20;
+    assert_eq!(map.remove::<A, _>(&1), std::option::Option::Some(A(20)));
+    assert_eq!(map.len(), 2);
+
+    assert_eq!(map.get_or_insert_with::<C>(7, || C(1)).0, 1);
+    assert_eq!(map.get_or_insert_with::<C>(7, || C(2)).0, 1);
+    match map.entry::<C>(7) {
+        ConcurrentEntry::Occupied(e) => assert_eq!(e.remove(), C(1)),
+        ConcurrentEntry::Vacant(_) => panic!("entry should be occupied"),
+    }
+    let _ = map.entry::<C>(7).and_modify(|c| c.0 += 1).or_insert(C(5));
+    assert_eq!(map.get::<C, _>(&7).map(|e| e.value.0), std::option::Option::Some(5));
+
+    let map = ConcurrentMap::<MultiValued>::new();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let map = &map;
+            let _ = s.spawn(move || {
+                for i in 0..100 {
+                    let _ = map.insert((t * 100 + i, A(i as i32)));
+                    map.entry::<B>(i).or_insert(B(0)).0 += 1;
+                }
+            });
+        }
+    });
+    assert_eq!(map.len(), 500);
+    assert!((0..100).all(|i| map.get::<B, _>(&i).map(|e| e.value.0) == std::option::Option::Some(4)));
+    map.clear();
+    assert!(map.is_empty());
+}
+
+#[test]
+#[cfg(all(feature = "std", loom))]
+fn test_concurrent_map_loom() {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    // Two threads racing to initialize and increment the same entry
+    loom::model(|| {
+        let map = Arc::new(ConcurrentMap::<Singleton>::with_shard_amount(2));
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let map = map.clone();
+                thread::spawn(move || map.get_or_insert_with::<D>((), || D(0)).0 += 1)
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(map.get_default::<D>().map(|e| e.some.0), std::option::Option::Some(2));
+    });
+    // Concurrent inserts into different shards, and a reader
+    loom::model(|| {
+        let map = Arc::new(ConcurrentMap::<MultiValued>::with_shard_amount(2));
+        let writer = {
+            let map = map.clone();
+            thread::spawn(move || {
+                let _ = map.insert((0, A(1)));
+                let _ = map.insert((1, A(2)));
+            })
+        };
+        let reader = {
+            let map = map.clone();
+            thread::spawn(move || {
+                // Release the read lock before the next lookup, the keys might share a shard
+                let seen = map.get::<A, _>(&1).map(|e| e.value.0);
+                if seen.is_some() {
+                    assert_eq!(seen, std::option::Option::Some(2));
+                    assert!(map.contains_key::<A, _>(&0));
+                }
+            })
+        };
+        writer.join().unwrap();
+        reader.join().unwrap();
+        assert_eq!(map.remove::<A, _>(&0), std::option::Option::Some(A(1)));
+        assert_eq!(map.len(), 1);
+    });
+}
+
+#[test]
+fn test_persistent_map() {
+    let empty = PersistentMap::<MultiValued>::new();
+    let mut map = empty.clone();
+    for i in 0..200 {
+        map = map.insert((i, A(i as i32))).insert((i, B(-(i as i32))));
+    }
+    assert!(empty.is_empty());
+    assert_eq!(map.len(), 400);
+    let snapshot = map.clone();
+    assert!(snapshot.ptr_eq(&map));
+    let updated = map.insert((7, A(70))).remove::<B, _>(&7).remove::<B, _>(&1000);
+    assert_eq!(updated.len(), 399);
+    assert_eq!(updated.get::<A, _>(&7).map(|e| e.value.0), std::option::Option::Some(70));
+    assert!(!updated.contains_key::<B, _>(&7));
+    assert_eq!(snapshot.get::<A, _>(&7).map(|e| e.value.0), std::option::Option::Some(7));
+    assert!(snapshot.contains_key::<B, _>(&7));
+    assert_eq!(updated.iter().count(), 399);
+    let removed = (0..200).fold(updated, |m, i| m.remove::<A, _>(&i).remove::<B, _>(&i));
+    assert!(removed.is_empty());
+    assert_eq!(removed.iter().len(), 0);
+
+    // All hashes collide, exercise the collision nodes
+    #[derive(Default)]
+    struct Constant;
+    impl std::hash::Hasher for Constant {
+        fn finish(&self) -> u64 { 0 }
+        fn write(&mut self, _: &[u8]) {}
+    }
+    type ConstantState = std::hash::BuildHasherDefault<Constant>;
+    let mut map = PersistentMap::<MultiValued, ConstantState>::default();
+    for i in 0..10 {
+        map = map.insert((i, A(i as i32)));
+    }
+    assert_eq!(map.len(), 10);
+    assert!((0..10).all(|i| map.get::<A, _>(&i).map(|e| e.value.0) == std::option::Option::Some(i as i32)));
+    let map = (0..9).fold(map, |m, i| m.remove::<A, _>(&i));
+    assert_eq!(map.get::<A, _>(&9).map(|e| e.value.0), std::option::Option::Some(9));
+    assert_eq!(map.iter().count(), 1);
+
+    let mut map: DebuggableMap<Singleton> = Default::default();
+    let _ = map.insert(A(1));
+    let _ = map.insert(B(2));
+    let map = PersistentMap::from(map);
+    assert_eq!(map.get_default::<B>().map(|e| e.some.0), std::option::Option::Some(2));
+    assert!(format!("{:?}", map).contains("A(1)"));
+}
+
+#[test]
+fn test_indexed_map() {
+    let mut map = IndexedMap::<MultiValued>::new();
+    assert_eq!(map.insert_full((0, C(0))), (0, None));
+    assert_eq!(map.insert_full((0, A(1))), (1, None));
+    let _ = map.insert((1, B(2)));
+    let _ = map.insert((2, A(3)));
+    let _ = map.insert((3, A(4)));
+    // Replacing keeps the position
+    assert_eq!(map.insert_full((0, A(10))).0, 1);
+    let order = |map: &IndexedMap<MultiValued>| -> Vec<&'static str> {
+        map.iter().map(|e| e.type_name().rsplit("::").next().unwrap()).collect()
+    };
+    assert_eq!(order(&map), ["C", "A", "B", "A", "A"]);
+    assert_eq!(map.get_index(1).and_then(|e| e.hash_entry::<A>()).map(|e| e.value.0), std::option::Option::Some(10));
+    assert_eq!(map.get_index_of::<A, _>(&2), std::option::Option::Some(3));
+
+    assert_eq!(map.swap_remove::<A, _>(&0).map(|e| e.value.0), std::option::Option::Some(10));
+    assert_eq!(order(&map), ["C", "A", "B", "A"]);
+    assert_eq!(map.get_index_of::<A, _>(&3), std::option::Option::Some(1));
+    assert_eq!(map.shift_remove::<C, _>(&0).map(|e| e.value.0), std::option::Option::Some(0));
+    assert_eq!(map.get_index_of::<A, _>(&2), std::option::Option::Some(2));
+    assert_eq!(map.get::<B, _>(&1).map(|e| e.value.0), std::option::Option::Some(2));
+    assert!(map.swap_remove::<B, _>(&7).is_none());
+
+    map.sort_by_type_name();
+    assert_eq!(order(&map), ["A", "A", "B"]);
+    assert_eq!(map.get_index_of::<B, _>(&1), std::option::Option::Some(2));
+    *map.get_mut::<A, _>(&3).unwrap() = A(40);
+    assert_eq!(map.first().and_then(|e| e.hash_entry::<A>()).map(|e| e.value.0), std::option::Option::Some(40));
+    for mut entry in &mut map {
+        if let std::option::Option::Some(a) = entry.get_mut::<A>() {
+            a.0 += 1;
+        }
+    }
+    *map.get_index_mut(2).and_then(|e| e.into_mut::<B>().ok()).unwrap() = B(20);
+    assert_eq!(map.get::<A, _>(&3).map(|e| e.value.0), std::option::Option::Some(41));
+    assert_eq!(map.get::<B, _>(&1).map(|e| e.value.0), std::option::Option::Some(20));
+
+    let mut map: IndexedMap<Singleton, DefaultHashBuilder, dyn CloneDebugEqHashableAny<DefaultHasher>> = Default::default();
+    trait CloneDebugEqHashableAny<H: std::hash::Hasher>: CloneableHashableAny<H> + DebugEntry + DynPartialEq {}
+    impl<H: std::hash::Hasher, T: CloneableHashableAny<H> + DebugEntry + DynPartialEq> CloneDebugEqHashableAny<H> for T {}
+    crate::create_entry_impl!(CloneDebugEqHashableAny<H> where EntryAt<E, A>: Clone + PartialEq, KeyAt<E, A>: Debug, ValueAt<E, A>: Debug,);
+    for i in 0..20i32 {
+        let _ = map.insert::<u32, _>(i as u32);
+        let _ = map.insert::<i32, _>(i);
+        let _ = map.insert::<i64, _>(i as i64);
+    }
+    let _ = map.insert(B(1));
+    let _ = map.insert(A(2));
+    let copy = map.clone();
+    let entry = |name: &str, value: &str| format!(r#"{{ type: "{}", key: () }}: {}"#, name, value);
+    let entries = [
+        entry(core::any::type_name::<u32>(), "19"),
+        entry(core::any::type_name::<i32>(), "19"),
+        entry(core::any::type_name::<i64>(), "19"),
+        entry(core::any::type_name::<B>(), "B(1)"),
+        entry(core::any::type_name::<A>(), "A(2)"),
+    ];
+    assert_eq!(format!("{:?}", copy), format!("{{ {}}}", entries.join(",  ")));
+    map.sort_by_type_name();
+    assert!(map == copy);
+    assert_ne!(format!("{:?}", map), format!("{:?}", copy));
+}
+
+#[test]
+fn test_inline_storage() {
+    #[derive(Clone, Debug, PartialEq)]
+    #[repr(align(32))]
+    struct Aligned(u8);
+    type Large = [u64; 8];
+
+    fn check<const N: usize>() {
+        let counter = std::rc::Rc::new(());
+        let mut map: Map<Singleton, DefaultHashBuilder, dyn CloneableHashableAny<DefaultHasher>, N> = Default::default();
+        let _ = map.insert(());
+        let _ = map.insert(1u8);
+        let _ = map.insert(2u16);
+        let _ = map.insert(3u32);
+        let _ = map.insert(4u64);
+        let _ = map.insert(counter.clone());
+        let _ = map.insert([5u64; 8]);
+        let _ = map.insert(Aligned(6));
+        assert_eq!(std::rc::Rc::strong_count(&counter), 2);
+        // entries survive being moved around when the table is resized
+        map.reserve(100);
+        map.shrink_to_fit();
+        let copy = map.clone();
+        assert_eq!(std::rc::Rc::strong_count(&counter), 3);
+        for map in [&map, &copy] {
+            assert_eq!(**map.get_default::<u8>().unwrap(), 1);
+            assert_eq!(**map.get_default::<u64>().unwrap(), 4);
+            assert_eq!(**map.get_default::<Large>().unwrap(), [5u64; 8]);
+            let aligned: &Aligned = map.get_default::<Aligned>().unwrap();
+            assert_eq!(*aligned, Aligned(6));
+            assert_eq!(aligned as *const Aligned as usize % 32, 0);
+        }
+        *map.get_default_mut::<Large>().unwrap() = [7u64; 8];
+        assert_eq!(**copy.get_default::<Large>().unwrap(), [5u64; 8]);
+        assert_eq!(map.remove::<Large, _>(&()), std::option::Option::Some([7u64; 8]));
+        assert_eq!(map.remove::<u16, _>(&()), std::option::Option::Some(2));
+        let rc = map.remove::<std::rc::Rc<()>, _>(&()).unwrap();
+        assert_eq!(std::rc::Rc::strong_count(&counter), 3);
+        drop(rc);
+        drop(copy);
+        assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+        let _ = map.insert(counter.clone());
+        drop(map);
+        assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+    }
+    // everything boxed, the default, and everything except over-aligned entries inline
+    check::<0>();
+    check::<DEFAULT_INLINE_WORDS>();
+    check::<16>();
+
+    let mut indexed: IndexedMap<Singleton, DefaultHashBuilder, DynStorage<DefaultHashBuilder>, 16> = Default::default();
+    let _ = indexed.insert([1u64; 8]);
+    assert_eq!(indexed.get_index_of::<[u64; 8], _>(&()), std::option::Option::Some(0));
+    #[cfg(all(feature = "std", not(loom)))]
+    {
+        let concurrent = ConcurrentMap::<Singleton, DefaultHashBuilder, dyn SendSyncHashableAny<DefaultHasher>, 0>::new();
+        let _ = concurrent.insert(2u8);
+        assert!(concurrent.contains_key::<u8, _>(&()));
+    }
+}
+
+#[test]
+#[cfg(not(feature = "unstable_features"))]
+fn test_create_entry_without_coerce() {
+    trait PlainHashableAny<H: std::hash::Hasher>: HashableAny<H> {}
+    impl<H: std::hash::Hasher, T: HashableAny<H>> PlainHashableAny<H> for T {}
+    // Written by hand without `coerce_entry`, so entries can't be stored inline and get boxed up
+    unsafe impl<H: std::hash::Hasher, A: 'static + ?Sized, E: 'static + ?Sized + EntryFamily<A>>
+        CreateEntry<A, E> for dyn PlainHashableAny<H>
+    {
+        fn from_entry(entry: EntryAt<E, A>) -> Box<Self> {
+            Box::new(InnerEntry::<E, A>::new(entry))
+        }
+    }
+
+    let counter = std::rc::Rc::new(());
+    let mut map = Map::<Singleton, DefaultHashBuilder, dyn PlainHashableAny<DefaultHasher>, 16>::new();
+    let _ = map.insert(1u8);
+    let _ = map.insert([2u64; 8]);
+    let _ = map.insert(counter.clone());
+    map.reserve(100);
+    assert_eq!(**map.get_default::<u8>().unwrap(), 1);
+    assert_eq!(**map.get_default::<[u64; 8]>().unwrap(), [2u64; 8]);
+    *map.get_default_mut::<u8>().unwrap() = 3;
+    assert_eq!(map.remove::<u8, _>(&()), std::option::Option::Some(3));
+    assert_eq!(std::rc::Rc::strong_count(&counter), 2);
+    drop(map);
+    assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+
+    // `ArrayMap` never boxes entries, so it refuses them instead
+    let mut map = ArrayMap::<Singleton, 2, DefaultHashBuilder, dyn PlainHashableAny<DefaultHasher>>::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| map.try_insert(1u8)));
+    assert!(result.is_err());
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_partitioned_map() {
+    let mut map = PartitionedMap::<MultiValued>::new();
+    assert!(map.is_empty());
+    for i in 0..10 {
+        assert!(map.insert((i, A(i as i32))).is_none());
+    }
+    assert!(map.insert((0, B(0))).is_none());
+    assert!(map.insert((1, B(1))).is_none());
+    assert_eq!(map.insert((1, B(10))).map(|e| e.value), std::option::Option::Some(B(1)));
+    assert_eq!(map.len(), 12);
+    assert_eq!(map.count_of::<A>(), 10);
+    assert_eq!(map.count_of::<B>(), 2);
+    assert!(!map.contains_type::<C>());
+    assert_eq!(map.get::<B, _>(&1).map(|e| &e.value), std::option::Option::Some(&B(10)));
+    assert!(map.get::<C, _>(&1).is_none());
+    *map.get_mut::<A, _>(&3).unwrap() = A(30);
+    assert_eq!(map.remove::<A, _>(&3), std::option::Option::Some(A(30)));
+    assert!(map.remove::<A, _>(&3).is_none());
+    assert_eq!(map.len(), 11);
+
+    map.retain_of::<A>(|&k, _| k % 2 == 0);
+    let mut keys: Vec<u32> = map.keys_of::<A>().copied().collect();
+    keys.sort_unstable();
+    assert_eq!(keys, [0, 2, 4, 6, 8]);
+    for (_, v) in map.iter_of_mut::<A>() {
+        v.0 += 1;
+    }
+    assert_eq!(map.values_of::<A>().map(|v| v.0).sum::<i32>(), 25);
+    assert_eq!(map.len(), 7);
+
+    // a panicking predicate keeps the entries it already removed removed
+    let mut copy = PartitionedMap::<MultiValued>::new();
+    for i in 0..10 {
+        let _ = copy.insert((i, A(i as i32)));
+    }
+    let mut calls = 0;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        copy.retain_of::<A>(|_, _| {
+            calls += 1;
+            assert!(calls < 5);
+            false
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(copy.count_of::<A>(), 6);
+    assert_eq!(copy.len(), 6);
+
+    let mut names: Vec<&str> = map.type_names().collect();
+    names.sort_unstable();
+    let mut expected = [core::any::type_name::<A>(), core::any::type_name::<B>()];
+    expected.sort_unstable();
+    assert_eq!(names, expected);
+    assert_eq!(map.remove_type::<A>(), 5);
+    assert_eq!(map.remove_type::<A>(), 0);
+    let mut taken = map.take_type::<B>();
+    taken.sort_unstable_by_key(|e| e.variant);
+    assert_eq!(taken.into_iter().map(|e| e.value).collect::<Vec<_>>(), [B(0), B(10)]);
+    assert!(map.is_empty());
+
+    let mut map = PartitionedMap::<Singleton>::default();
+    let _ = map.insert(A(1));
+    let _ = map.insert(B(2));
+    assert!(map.contains_default::<A>());
+    assert_eq!(**map.get_default::<B>().unwrap(), B(2));
+    map.clear();
+    assert!(map.is_empty());
+    assert!(!map.contains_default::<A>());
+}
+
+#[test]
+fn test_type_map() {
+    let mut map = TypeMap::<dyn CloneableHashableAny<DefaultHasher>>::new();
+    assert_eq!(map.insert(A(1)), None);
+    assert_eq!(map.insert(B(2)), None);
+    assert_eq!(map.insert(A(3)), std::option::Option::Some(A(1)));
+    assert_eq!(map.insert([4u64; 8]), None);
+    assert_eq!(map.len(), 3);
+    assert!(map.contains::<B>());
+    assert!(!map.contains::<C>());
+    assert_eq!(map.get::<A>(), std::option::Option::Some(&A(3)));
+    assert_eq!(map.get::<C>(), None);
+    map.get_mut::<B>().unwrap().0 = 20;
+    *map.entry::<C>().or_insert(C(5)) = C(50);
+    let _ = map.entry::<C>().and_modify(|c| c.0 += 1).or_insert(C(0));
+    assert_eq!(map.get::<C>(), std::option::Option::Some(&C(51)));
+    assert_eq!(*map.entry::<u32>().or_default(), 0);
+
+    let copy = map.clone();
+    assert_eq!(map.remove::<B>(), std::option::Option::Some(B(20)));
+    assert_eq!(map.remove::<B>(), None);
+    match map.entry::<[u64; 8]>() {
+        type_map::Entry::Occupied(entry) => assert_eq!(entry.remove(), [4u64; 8]),
+        type_map::Entry::Vacant(_) => panic!("the entry was inserted before"),
+    }
+    assert_eq!(map.len(), 3);
+    assert_eq!(copy.len(), 5);
+    assert_eq!(copy.get::<B>(), std::option::Option::Some(&B(20)));
+    assert_eq!(copy.get::<[u64; 8]>(), std::option::Option::Some(&[4u64; 8]));
+
+    // values with interior mutability can be modified through shared references
+    let mut map = TypeMap::<DynStorage<DefaultHashBuilder>>::new();
+    let _ = map.insert(std::cell::Cell::new(1u32));
+    let _ = map.insert(std::cell::RefCell::new(vec![1u32]));
+    map.get::<std::cell::Cell<u32>>().unwrap().set(2);
+    map.get::<std::cell::RefCell<Vec<u32>>>().unwrap().borrow_mut().push(2);
+    match map.entry::<std::cell::Cell<u32>>() {
+        type_map::Entry::Occupied(entry) => entry.get().set(entry.get().get() + 1),
+        type_map::Entry::Vacant(_) => panic!("the entry was inserted before"),
+    }
+    assert_eq!(map.remove::<std::cell::Cell<u32>>().map(std::cell::Cell::into_inner), std::option::Option::Some(3));
+    assert_eq!(*map.get::<std::cell::RefCell<Vec<u32>>>().unwrap().borrow(), [1, 2]);
+
+    let mut map = DebuggableMap::<Singleton>::new();
+    let _ = map.insert(A(1));
+    let _ = map.insert(B(2));
+    let map = TypeMap::from(map);
+    assert_eq!(map.get::<A>(), std::option::Option::Some(&A(1)));
+    let debug = format!("{:?}", map);
+    let a = format!(r#"{{ type: "{}", key: () }}: A(1)"#, core::any::type_name::<A>());
+    let b = format!(r#"{{ type: "{}", key: () }}: B(2)"#, core::any::type_name::<B>());
+    assert!(debug.contains(&a));
+    assert!(debug.contains(&b));
+}
+
+#[test]
+fn test_frozen_map() {
+    trait CloneEqHashableAny<H: std::hash::Hasher>: CloneableHashableAny<H> + DynPartialEq {}
+    impl<H: std::hash::Hasher, T: CloneableHashableAny<H> + DynPartialEq> CloneEqHashableAny<H> for T {}
+    crate::create_entry_impl!(CloneEqHashableAny<H> where EntryAt<E, A>: Clone + PartialEq,);
+    let mut map = Map::<MultiValued, DefaultHashBuilder, dyn CloneEqHashableAny<DefaultHasher>>::new();
+    for i in 0..500 {
+        let _ = map.insert((i, A(i as i32)));
+        let _ = map.insert((i, B(-(i as i32))));
+    }
+    let _ = map.insert((7, C(7)));
+    let copy = map.clone();
+    let mut frozen = map.freeze();
+    assert_eq!(frozen.len(), 1001);
+    for i in 0..500 {
+        assert_eq!(frozen.get::<A, _>(&i).map(|e| &e.value), std::option::Option::Some(&A(i as i32)));
+        assert_eq!(frozen.get::<B, _>(&i).map(|e| &e.value), std::option::Option::Some(&B(-(i as i32))));
+    }
+    assert!(frozen.contains_key::<C, _>(&7));
+    assert!(!frozen.contains_key::<C, _>(&8));
+    assert!(!frozen.contains_key::<A, _>(&500));
+    *frozen.get_mut::<A, _>(&3).unwrap() = A(30);
+    assert_eq!(frozen.get::<A, _>(&3).map(|e| &e.value), std::option::Option::Some(&A(30)));
+    for mut entry in frozen.iter_mut() {
+        if let std::option::Option::Some(c) = entry.get_mut::<C>() {
+            c.0 += 1;
+        }
+    }
+    assert_eq!(frozen.get::<C, _>(&7).map(|e| &e.value), std::option::Option::Some(&C(8)));
+    for mut entry in frozen.iter_mut() {
+        if let std::option::Option::Some(c) = entry.get_mut::<C>() {
+            c.0 -= 1;
+        }
+    }
+
+    let mut thawed = frozen.clone().thaw();
+    assert!(thawed != copy);
+    let _ = thawed.insert((3, A(3)));
+    assert!(thawed == copy);
+    assert!(frozen != copy.clone().freeze());
+    *frozen.get_mut::<A, _>(&3).unwrap() = A(3);
+    assert!(frozen == copy.freeze());
+
+    // Entries whose hashes collide completely still have to be found
+    #[derive(Default)]
+    struct Collide;
+    impl std::hash::Hasher for Collide {
+        fn write(&mut self, _: &[u8]) {}
+        fn finish(&self) -> u64 {
+            42
+        }
+    }
+    impl std::hash::BuildHasher for Collide {
+        type Hasher = Collide;
+        fn build_hasher(&self) -> Collide {
+            Collide
+        }
+    }
+    let mut map = Map::<MultiValued, Collide>::default();
+    for i in 0..10 {
+        let _ = map.insert((i, A(i as i32)));
+    }
+    let frozen = map.freeze();
+    assert_eq!((0..10).filter(|i| frozen.contains_key::<A, _>(i)).count(), 10);
+    assert!(!frozen.contains_key::<B, _>(&0));
+
+    let frozen = DebuggableMap::<Singleton>::new().freeze();
+    assert!(frozen.is_empty());
+    assert_eq!(frozen.get_default::<A>(), None);
+    assert_eq!(format!("{:?}", frozen), "{}");
+}
+
+#[test]
+fn test_array_map() {
+    let mut map = ArrayMap::<MultiValued, 4>::new();
+    assert_eq!(map.capacity(), 4);
+    for i in 0..4 {
+        assert!(map.try_insert((i, A(i as i32))).unwrap().is_none());
+    }
+    assert!(map.is_full());
+    let rejected = map.try_insert((4, A(4))).unwrap_err().into_inner();
+    assert_eq!(rejected.variant, 4);
+    // Replacing and modifying existing entries still works when full
+    assert_eq!(map.try_insert((1, A(10))).unwrap().map(|e| e.value), std::option::Option::Some(A(1)));
+    *map.entry::<A>(2).unwrap().or_insert(A(0)) = A(20);
+    assert_eq!(map.get::<A, _>(&2).map(|e| &e.value), std::option::Option::Some(&A(20)));
+    assert_eq!(map.entry::<B>(0).err().map(array::FullError::into_inner), std::option::Option::Some(0));
+
+    assert_eq!(map.remove::<A, _>(&0), std::option::Option::Some(A(0)));
+    assert_eq!(map.remove::<A, _>(&0), None);
+    assert_eq!(map.len(), 3);
+    *map.entry::<B>(0).unwrap().or_insert(B(0)) = B(5);
+    assert_eq!(map.get_mut::<B, _>(&0), std::option::Option::Some(&mut B(5)));
+    assert!(map.contains_key::<A, _>(&3));
+    assert!(!map.contains_key::<B, _>(&3));
+    assert_eq!(map.iter().filter(|e| e.is::<A>()).count(), 3);
+    for mut entry in map.iter_mut() {
+        if let std::option::Option::Some(a) = entry.get_mut::<A>() {
+            a.0 *= 10;
+        }
+    }
+    assert_eq!(map.get::<A, _>(&3).map(|e| &e.value), std::option::Option::Some(&A(30)));
+
+    let counter = std::rc::Rc::new(());
+    let mut map = ArrayMap::<Singleton, 2, DefaultHashBuilder, dyn DebugHashableAny<DefaultHasher>>::new();
+    assert!(map.try_insert(counter.clone()).unwrap().is_none());
+    assert_eq!(*map.entry_default::<u32>().unwrap().or_default(), 0);
+    assert!(map.try_insert(1.5f32).is_err());
+    assert_eq!(std::rc::Rc::strong_count(&counter), 2);
+    assert!(format!("{:?}", map).contains("u32"));
+    map.clear();
+    assert!(map.is_empty());
+    assert_eq!(std::rc::Rc::strong_count(&counter), 1);
+}
+
+#[cfg(feature = "bumpalo")]
+#[test]
+fn test_arena_map() {
+    use std::rc::Rc;
+    struct Large([u64; 8], #[allow(dead_code)] Rc<()>);
+    let counter = Rc::new(());
+    let mut map = ArenaMap::<MultiValued>::with_arena(bumpalo::Bump::with_capacity(1024));
+    let before = map.arena().allocated_bytes();
+    // Small entries are still stored inline
+    let _ = map.insert((0, A(0)));
+    assert_eq!(map.arena().allocated_bytes(), before);
+    for i in 0..8 {
+        assert!(map.insert((i, Large([i.into(); 8], counter.clone()))).is_none());
+    }
+    assert_eq!(Rc::strong_count(&counter), 9);
+    assert_eq!(map.len(), 9);
+    assert_eq!(map.get::<Large, _>(&3).map(|e| e.value.0[7]), std::option::Option::Some(3));
+    map.get_mut::<Large, _>(&3).unwrap().0[7] = 30;
+    assert_eq!(map.get::<Large, _>(&3).map(|e| e.value.0[7]), std::option::Option::Some(30));
+    let old = map.insert((3, Large([0; 8], counter.clone()))).unwrap();
+    assert_eq!(old.value.0[7], 30);
+    drop(old);
+    assert_eq!(map.remove::<Large, _>(&5).map(|l| l.0[0]), std::option::Option::Some(5));
+    assert_eq!(Rc::strong_count(&counter), 8);
+    assert_eq!(map.iter().filter(|e| e.is::<Large>()).count(), 7);
+    assert!(map.as_map().contains_key::<A, _>(&0));
+
+    map.clear();
+    assert!(map.is_empty());
+    assert_eq!(Rc::strong_count(&counter), 1);
+    let _ = map.insert((1, Large([1; 8], counter.clone())));
+    let arena = map.into_arena();
+    assert_eq!(Rc::strong_count(&counter), 1);
+    assert!(arena.allocated_bytes() >= before);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_type_registry() {
+    use crate::registry::{TypeRegistry, UnknownTypes};
+    let mut registry = TypeRegistry::<MultiValued>::new();
+    let _ = registry.register::<A>("a").register::<B>("b");
+    assert_eq!(registry.tag_of::<B>(), std::option::Option::Some("b"));
+    assert_eq!(registry.tag_of::<C>(), None);
+
+    let mut map = Map::<MultiValued>::new();
+    let _ = map.insert((1, A(1)));
+    let _ = map.insert((2, A(2)));
+    let _ = map.insert((1, B(-1)));
+    let json = serde_json::to_value(registry.tagged(&map)).unwrap();
+    assert_eq!(json["a"].as_array().map(Vec::len), std::option::Option::Some(2));
+    assert_eq!(json["b"], serde_json::json!([{ "value": -1, "variant": 1 }]));
+
+    let copy = registry.deserialize_map(&json).unwrap();
+    assert_eq!(copy.len(), 3);
+    assert_eq!(copy.get::<A, _>(&2).map(|e| &e.value), std::option::Option::Some(&A(2)));
+    assert_eq!(copy.get::<B, _>(&1).map(|e| &e.value), std::option::Option::Some(&B(-1)));
+
+    // Unregistered types and unknown tags fail, unless they are skipped
+    let _ = map.insert((1, C(1)));
+    assert!(serde_json::to_value(registry.tagged(&map)).is_err());
+    let mut unknown = json.clone();
+    unknown["c"] = serde_json::json!([{ "value": 1, "variant": 1 }]);
+    assert!(registry.deserialize_map(&unknown).is_err());
+    let registry = registry.with_unknown_types(UnknownTypes::Skip);
+    let skipped = serde_json::to_value(registry.tagged(&map)).unwrap();
+    assert_eq!(skipped.as_object().map(|o| o.len()), std::option::Option::Some(2));
+    assert_eq!(skipped["a"].as_array().map(Vec::len), std::option::Option::Some(2));
+    let mut copy = registry.deserialize_map(&unknown).unwrap();
+    assert_eq!(copy.len(), 3);
+
+    // Deserializing into an existing map replaces entries with equal keys
+    let _ = copy.insert((1, A(10)));
+    let _ = copy.insert((3, A(3)));
+    registry.deserialize_into(&mut copy, &json).unwrap();
+    assert_eq!(copy.len(), 4);
+    assert_eq!(copy.get::<A, _>(&1).map(|e| &e.value), std::option::Option::Some(&A(1)));
+    assert!(registry.deserialize_map(serde_json::json!({ "a": [{ "value": "x" }] })).is_err());
+}
+
+#[cfg(feature = "snapshot")]
+#[test]
+fn test_snapshot() {
+    use crate::registry::{TypeRegistry, UnknownTypes, snapshot::SnapshotError};
+    let mut old = TypeRegistry::<MultiValued>::new();
+    let _ = old.register::<A>("a").register::<B>("b");
+
+    let mut map = Map::<MultiValued>::new();
+    let _ = map.insert((1, A(1)));
+    let _ = map.insert((2, A(2)));
+    let _ = map.insert((1, B(-1)));
+    let bytes = old.write_snapshot(&map).unwrap();
+    let copy = old.read_snapshot(&bytes).unwrap();
+    assert_eq!(copy.len(), 3);
+    assert_eq!(copy.get::<A, _>(&2).map(|e| &e.value), std::option::Option::Some(&A(2)));
+    assert_eq!(copy.get::<B, _>(&1).map(|e| &e.value), std::option::Option::Some(&B(-1)));
+
+    // Older schema versions are migrated, and newer ones are rejected
+    let mut new = TypeRegistry::<MultiValued>::new();
+    let _ = new.register_version::<A>("a", 1).register::<B>("b");
+    assert!(matches!(
+        new.read_snapshot(&bytes),
+        Err(SnapshotError::MissingMigration { version: 0, .. })
+    ));
+    let _ = new.migrate::<A, (i32, u32)>(0, |(value, variant)| Multiple { value: A(value * 10), variant });
+    assert_eq!(new.version_of::<A>(), std::option::Option::Some(1));
+    let copy = new.read_snapshot(&bytes).unwrap();
+    assert_eq!(copy.get::<A, _>(&2).map(|e| &e.value), std::option::Option::Some(&A(20)));
+    assert_eq!(copy.get::<B, _>(&1).map(|e| &e.value), std::option::Option::Some(&B(-1)));
+    let newer = new.write_snapshot(&copy).unwrap();
+    assert!(matches!(
+        old.read_snapshot(&newer),
+        Err(SnapshotError::FutureVersion { version: 1, current: 0, .. })
+    ));
+
+    // Damaged input fails with an error instead of panicking
+    assert_eq!(old.read_snapshot(b"JSON{}").err(), std::option::Option::Some(SnapshotError::BadMagic));
+    for len in 0..bytes.len() {
+        assert!(old.read_snapshot(&bytes[..len]).is_err());
+    }
+    assert!(matches!(
+        old.read_snapshot(&bytes[..bytes.len() - 1]),
+        Err(SnapshotError::Truncated { .. })
+    ));
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(
+        old.read_snapshot(&longer).err(),
+        std::option::Option::Some(SnapshotError::TrailingBytes { len: 1 })
+    );
+    for i in 0..bytes.len() {
+        let mut corrupt = bytes.clone();
+        corrupt[i] ^= 0x55;
+        assert!(old.read_snapshot(&corrupt).is_err());
+    }
+    let mut corrupt = bytes.clone();
+    corrupt[10] ^= 1;
+    assert!(matches!(old.read_snapshot(&corrupt), Err(SnapshotError::ChecksumMismatch { .. })));
+    let mut mismatched = TypeRegistry::<MultiValued>::new();
+    let _ = mismatched.register::<bool>("a").register::<B>("b");
+    assert!(matches!(mismatched.read_snapshot(&bytes), Err(SnapshotError::Entry { .. })));
+
+    // Unregistered types and unknown tags fail, unless they are skipped
+    let _ = map.insert((1, C(1)));
+    assert!(matches!(old.write_snapshot(&map), Err(SnapshotError::Unregistered { .. })));
+    let mut all = TypeRegistry::<MultiValued>::new();
+    let _ = all.register::<A>("a").register::<B>("b").register::<C>("c");
+    let bytes = all.write_snapshot(&map).unwrap();
+    assert_eq!(
+        old.read_snapshot(&bytes).err(),
+        std::option::Option::Some(SnapshotError::UnknownTag { tag: "c".into() })
+    );
+    let old = old.with_unknown_types(UnknownTypes::Skip);
+    assert_eq!(old.read_snapshot(&bytes).unwrap().len(), 3);
+    assert_eq!(old.read_snapshot(&old.write_snapshot(&map).unwrap()).unwrap().len(), 3);
+}
@@ -2,8 +2,36 @@
 //! The variants found in this module are not exhaustive. If you want your own variant,
 //! consider using [`create_entry_impl`] and your own trait. Most methods on [`Map`] are
 //! guarded behind the trait extending from [`HashableAny`].
-use std::hash::{BuildHasher, Hasher};
-use crate::{DebugEntry, DefaultHashBuilder, DynClone, DynPartialEq, HashableAny, Map};
+use core::borrow::Borrow;
+use core::cell::{Ref, RefMut};
+use core::fmt::{Debug, Display, Formatter};
+use core::hash::{BuildHasher, Hash, Hasher};
+use crate::families::{CellEntry, Celled};
+use crate::{
+    CreateEntry, DebugEntry, DefaultHashBuilder, DynClone, DynPartialEq, EntryAt, EntryFamily,
+    HashEntry, HashableAny, KeyAt, Map, ValueAt,
+};
+
+#[cfg(feature = "bumpalo")]
+pub mod arena;
+pub mod array;
+#[cfg(feature = "std")]
+pub mod concurrent;
+pub mod frozen;
+pub mod indexed;
+pub mod partitioned;
+pub mod persistent;
+pub mod type_map;
+#[cfg(feature = "bumpalo")]
+pub use arena::ArenaMap;
+pub use array::ArrayMap;
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentMap;
+pub use frozen::FrozenMap;
+pub use indexed::IndexedMap;
+pub use partitioned::PartitionedMap;
+pub use persistent::PersistentMap;
+pub use type_map::TypeMap;
 
 /// Glue trait
 /// 
@@ -47,8 +75,371 @@ pub type ComparableMap<E, S = DefaultHashBuilder> = Map<E, S, PartialEqDynStorag
 /// third argument to [`Map`].
 pub type DebuggableMap<E, S = DefaultHashBuilder> = Map<E, S, DebugDynStorage<S>>;
 
-#[allow(unused_imports)]
-use std::fmt::Debug;
 crate::create_entry_impl!(CloneableHashableAny<H> where crate::EntryAt<E, A>: Clone,);
 crate::create_entry_impl!(PartialEqHashableAny<H> where crate::EntryAt<E, A>: PartialEq,);
 crate::create_entry_impl!(DebugHashableAny<H> where crate::KeyAt<E, A>: Debug, crate::ValueAt<E, A>: Debug,);
+
+// Thread-safe variants. Adding `+ Send` to the trait object directly only gets a `CreateEntry`
+// impl with `unstable_features`, so each combination gets its own glue trait instead.
+macro_rules! thread_safe_variants {
+    ($(
+        $glue:ident: $capability:ident $(+ $other_capabilities:ident)* => $alias:ident, $desc:literal
+        where $($bounded_type:ty: $bound:tt $(+ $other_bounds:tt)*),+;
+    )*) => {$(
+        #[doc = concat!("Glue trait for entries that are ", $desc, ".")]
+        ///
+        /// If you get an error mentioned that this is not implemented, make sure the entries
+        /// you insert satisfy the bounds, see
+        #[doc = concat!("[`", stringify!($alias), "`].")]
+        pub trait $glue<H: Hasher>: HashableAny<H> + $capability $(+ $other_capabilities)* {}
+        impl<H: Hasher, T> $glue<H> for T where T: HashableAny<H> + $capability $(+ $other_capabilities)* {}
+
+        #[doc = concat!("Type-alias for a [`Map`] whose entries are ", $desc, ".")]
+        pub type $alias<E, S = DefaultHashBuilder> = Map<E, S, dyn $glue<<S as BuildHasher>::Hasher>>;
+
+        crate::create_entry_impl!($glue<H> where $($bounded_type: $bound $(+ $other_bounds)*,)+);
+    )*};
+}
+
+thread_safe_variants! {
+    SendHashableAny: Send => SendMap, "[`Send`]"
+        where crate::InnerEntry<E, A>: Send;
+    SyncHashableAny: Sync => SyncMap, "[`Sync`]"
+        where crate::InnerEntry<E, A>: Sync;
+    SendSyncHashableAny: Send + Sync => SendSyncMap, "[`Send`] and [`Sync`]"
+        where crate::InnerEntry<E, A>: Send + Sync;
+    CloneableSendHashableAny: DynClone + Send => CloneableSendMap, "[`Send`] and can be cloned"
+        where crate::EntryAt<E, A>: Clone, crate::InnerEntry<E, A>: Send;
+    CloneableSyncHashableAny: DynClone + Sync => CloneableSyncMap, "[`Sync`] and can be cloned"
+        where crate::EntryAt<E, A>: Clone, crate::InnerEntry<E, A>: Sync;
+    CloneableSendSyncHashableAny: DynClone + Send + Sync => CloneableSendSyncMap,
+        "[`Send`], [`Sync`] and can be cloned"
+        where crate::EntryAt<E, A>: Clone, crate::InnerEntry<E, A>: Send + Sync;
+    PartialEqSendHashableAny: DynPartialEq + Send => ComparableSendMap,
+        "[`Send`] and can be equality compared"
+        where crate::EntryAt<E, A>: PartialEq, crate::InnerEntry<E, A>: Send;
+    PartialEqSyncHashableAny: DynPartialEq + Sync => ComparableSyncMap,
+        "[`Sync`] and can be equality compared"
+        where crate::EntryAt<E, A>: PartialEq, crate::InnerEntry<E, A>: Sync;
+    PartialEqSendSyncHashableAny: DynPartialEq + Send + Sync => ComparableSendSyncMap,
+        "[`Send`], [`Sync`] and can be equality compared"
+        where crate::EntryAt<E, A>: PartialEq, crate::InnerEntry<E, A>: Send + Sync;
+    DebugSendHashableAny: DebugEntry + Send => DebuggableSendMap, "[`Send`] and implement [`Debug`]"
+        where crate::KeyAt<E, A>: Debug, crate::ValueAt<E, A>: Debug, crate::InnerEntry<E, A>: Send;
+    DebugSyncHashableAny: DebugEntry + Sync => DebuggableSyncMap, "[`Sync`] and implement [`Debug`]"
+        where crate::KeyAt<E, A>: Debug, crate::ValueAt<E, A>: Debug, crate::InnerEntry<E, A>: Sync;
+    DebugSendSyncHashableAny: DebugEntry + Send + Sync => DebuggableSendSyncMap,
+        "[`Send`], [`Sync`] and implement [`Debug`]"
+        where
+            crate::KeyAt<E, A>: Debug,
+            crate::ValueAt<E, A>: Debug,
+            crate::InnerEntry<E, A>: Send + Sync;
+}
+
+/// The error returned when runtime-borrowing an entry of a [`CellMap`] fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorrowError {
+    /// There is no entry at the requested key. Contains the name of the argument type.
+    Missing(&'static str),
+    /// The entry is already borrowed in a conflicting way. Contains the name of the argument type.
+    Conflict(&'static str),
+}
+
+impl Display for BorrowError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BorrowError::Missing(name) => write!(f, "no entry for {} in the map", name),
+            BorrowError::Conflict(name) => write!(f, "entry for {} is already borrowed", name),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BorrowError {}
+
+/// A [`Map`] where each value lives in a [`RefCell`], so that entries can be borrowed mutably
+/// from a shared reference. Borrows are checked at runtime, similar to [`RefCell`].
+///
+/// This allows holding mutable references to several entries at once, for example to
+/// hand out different resources to different systems. The third argument is the storage
+/// type of the inner `Map<Celled<E>, S, I>`, so e.g. capturing [`DynClone`] makes this
+/// map cloneable.
+///
+/// [`RefCell`]: core::cell::RefCell
+pub struct CellMap<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = dyn HashableAny<<S as BuildHasher>::Hasher>,
+> {
+    map: Map<Celled<E>, S, I>,
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> CellMap<E, S, I> {
+    #[inline]
+    /// Create a new, empty, [`CellMap`].
+    pub fn new() -> Self
+    where
+        S: Default,
+    {
+        Self { map: Map::new() }
+    }
+    #[inline]
+    /// Create a new, empty, [`CellMap`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self {
+            map: Map::with_hasher(hash_state),
+        }
+    }
+    #[inline]
+    /// Get the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    #[inline]
+    /// Check if the map is empty, i.e. `len() == 0`.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    #[inline]
+    /// Clear the map, but preserve the currently reserved capacity.
+    pub fn clear(&mut self) {
+        self.map.clear()
+    }
+    #[inline]
+    /// Get the underlying map, whose values are stored in [`RefCell`]s.
+    ///
+    /// [`RefCell`]: core::cell::RefCell
+    pub fn as_map(&self) -> &Map<Celled<E>, S, I> {
+        &self.map
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> CellMap<E, S, I> {
+    #[inline]
+    /// Check if the map contains a value for the specified key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.contains_key::<A, Q>(k)
+    }
+    /// Inserts an entry into the map.
+    ///
+    /// If the map did not have this key present, [`None`] is returned.
+    ///
+    /// Otherwise, the entry is fully replaced and `Some(old)` where `old` is the old entry is returned.
+    ///
+    /// [`None`]: core::option::Option::None
+    pub fn insert<A: 'static + ?Sized, P>(&mut self, entry: P) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, Celled<E>>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let entry: CellEntry<EntryAt<E, A>> = entry.into().into();
+        self.map.insert::<A, _>(entry).map(|old| old.into_inner())
+    }
+    /// Removes a key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    pub fn remove_entry<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.remove_entry::<A, Q>(k).map(|old| old.into_inner())
+    }
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.remove::<A, Q>(k).map(|old| old.into_inner())
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the key.
+    ///
+    /// Since this borrows the map mutably, no runtime check is necessary.
+    pub fn get_mut<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.map.get_mut::<A, Q>(k) {
+            Some(cell) => Some(cell.get_mut()),
+            None => None,
+        }
+    }
+    /// Immutably borrows the value corresponding to the key.
+    ///
+    /// Fails if there is no such entry, or if the entry is currently mutably borrowed.
+    pub fn try_borrow_key<A: 'static + ?Sized, Q: ?Sized>(
+        &self,
+        k: &Q,
+    ) -> Result<Ref<'_, ValueAt<E, A>>, BorrowError>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let name = core::any::type_name::<A>();
+        match self.map.get::<A, Q>(k) {
+            Some(entry) => match entry.split_ref().1.try_borrow() {
+                Ok(r) => Ok(r),
+                Err(_) => Err(BorrowError::Conflict(name)),
+            },
+            None => Err(BorrowError::Missing(name)),
+        }
+    }
+    /// Mutably borrows the value corresponding to the key.
+    ///
+    /// Fails if there is no such entry, or if the entry is currently borrowed.
+    pub fn try_borrow_key_mut<A: 'static + ?Sized, Q: ?Sized>(
+        &self,
+        k: &Q,
+    ) -> Result<RefMut<'_, ValueAt<E, A>>, BorrowError>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let name = core::any::type_name::<A>();
+        match self.map.get::<A, Q>(k) {
+            Some(entry) => match entry.split_ref().1.try_borrow_mut() {
+                Ok(r) => Ok(r),
+                Err(_) => Err(BorrowError::Conflict(name)),
+            },
+            None => Err(BorrowError::Missing(name)),
+        }
+    }
+    #[inline]
+    /// Immutably borrows the value corresponding to the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such entry, or if the entry is currently mutably borrowed.
+    /// For a non-panicking variant, use [`CellMap::try_borrow_key`].
+    pub fn borrow_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Ref<'_, ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        match self.try_borrow_key::<A, Q>(k) {
+            Ok(r) => r,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[inline]
+    /// Mutably borrows the value corresponding to the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such entry, or if the entry is currently borrowed.
+    /// For a non-panicking variant, use [`CellMap::try_borrow_key_mut`].
+    pub fn borrow_key_mut<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> RefMut<'_, ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        match self.try_borrow_key_mut::<A, Q>(k) {
+            Ok(r) => r,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[inline]
+    /// Immutably borrows the value corresponding to the default key.
+    /// See [`CellMap::try_borrow_key`].
+    pub fn try_borrow<A: 'static + ?Sized>(&self) -> Result<Ref<'_, ValueAt<E, A>>, BorrowError>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.try_borrow_key::<A, _>(&Default::default())
+    }
+    #[inline]
+    /// Mutably borrows the value corresponding to the default key.
+    /// See [`CellMap::try_borrow_key_mut`].
+    pub fn try_borrow_mut<A: 'static + ?Sized>(
+        &self,
+    ) -> Result<RefMut<'_, ValueAt<E, A>>, BorrowError>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.try_borrow_key_mut::<A, _>(&Default::default())
+    }
+    #[inline]
+    /// Immutably borrows the value corresponding to the default key.
+    /// See [`CellMap::borrow_key`].
+    pub fn borrow<A: 'static + ?Sized>(&self) -> Ref<'_, ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.borrow_key::<A, _>(&Default::default())
+    }
+    #[inline]
+    /// Mutably borrows the value corresponding to the default key.
+    /// See [`CellMap::borrow_key_mut`].
+    pub fn borrow_mut<A: 'static + ?Sized>(&self) -> RefMut<'_, ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.borrow_key_mut::<A, _>(&Default::default())
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher + Default, I: ?Sized + HashableAny<S::Hasher>> Default
+    for CellMap<E, S, I>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Clone for CellMap<E, S, I>
+where
+    Map<Celled<E>, S, I>: Clone,
+{
+    /// Clones all entries of the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any entry is currently mutably borrowed.
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> Debug for CellMap<E, S, I>
+where
+    Map<Celled<E>, S, I>: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.map.fmt(f)
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> PartialEq for CellMap<E, S, I>
+where
+    Map<Celled<E>, S, I>: PartialEq,
+{
+    #[inline]
+    fn eq(&self, rhs: &Self) -> bool {
+        self.map == rhs.map
+    }
+}
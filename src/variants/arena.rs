@@ -0,0 +1,237 @@
+//! An [`ArenaMap`], allocating its entries in a [`bumpalo`] arena instead of boxing them.
+//!
+//! Available with the `bumpalo` feature.
+use bumpalo::Bump;
+use core::borrow::Borrow;
+use core::fmt::{Debug, Formatter};
+use core::hash::{BuildHasher, Hash};
+use crate::{
+    CreateEntry, DefaultHashBuilder, DynStorage, DEFAULT_INLINE_WORDS, EntryAt, EntryFamily, ErasedEntry, HashableAny,
+    KeyAt, Map, ValueAt,
+};
+
+/// A [`Map`] whose entries are allocated in an arena, and freed all at once.
+///
+/// Entries small enough are still stored inline in the table, just like in a [`Map`]. Larger
+/// entries are bump allocated in the arena instead of being boxed up. Removing an entry drops
+/// it, but its memory is only reclaimed by [`ArenaMap::clear`], which resets the arena. This
+/// makes building and dropping many short-lived maps cheap, for example once per frame.
+///
+/// The arena is owned by the map, pass a pre-sized arena with [`ArenaMap::with_arena`] to
+/// avoid allocating while the map is in use, and reuse it with [`ArenaMap::into_arena`].
+pub struct ArenaMap<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    // Declared before `arena`, so that the entries are dropped before the memory they live in.
+    // Never hand out owned or mutable access to the entries, they must not outlive the arena.
+    map: Map<E, S, I, N>,
+    arena: Bump,
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    ArenaMap<E, S, I, N>
+{
+    #[inline]
+    /// Create a new, empty, [`ArenaMap`] with a new arena.
+    pub fn new() -> Self
+    where
+        S: Default,
+    {
+        Self::with_arena(Bump::new())
+    }
+    #[inline]
+    /// Create a new, empty, [`ArenaMap`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self::with_arena_and_hasher(Bump::new(), hash_state)
+    }
+    #[inline]
+    /// Create a new, empty, [`ArenaMap`] allocating its entries in the given arena.
+    pub fn with_arena(arena: Bump) -> Self
+    where
+        S: Default,
+    {
+        Self::with_arena_and_hasher(arena, S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`ArenaMap`] allocating its entries in the given arena, and using
+    /// the given hash builder to hash keys.
+    pub fn with_arena_and_hasher(arena: Bump, hash_state: S) -> Self {
+        Self {
+            map: Map::with_hasher(hash_state),
+            arena,
+        }
+    }
+    #[inline]
+    /// Get the arena the entries are allocated in.
+    pub fn arena(&self) -> &Bump {
+        &self.arena
+    }
+    #[inline]
+    /// Drop all entries and take back the arena, e.g. to reuse its memory for another map.
+    pub fn into_arena(self) -> Bump {
+        let Self { map, arena } = self;
+        drop(map);
+        arena
+    }
+    #[inline]
+    /// Get the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    #[inline]
+    /// Check if the map is empty, i.e. `len() == 0`.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    #[inline]
+    /// Clear the map, and free the memory of all entries at once by resetting the arena.
+    ///
+    /// The currently reserved capacity of the table, and the largest chunk of the arena,
+    /// are kept for reuse.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.arena.reset();
+    }
+    #[inline]
+    /// Get the underlying map.
+    pub fn as_map(&self) -> &Map<E, S, I, N> {
+        &self.map
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    ArenaMap<E, S, I, N>
+{
+    #[inline]
+    /// An iterator visiting all entries in arbitrary order.
+    pub fn iter(&self) -> impl '_ + Iterator<Item = &'_ ErasedEntry<E, I, N>> {
+        self.map.iter()
+    }
+    #[inline]
+    /// Check if the map contains a value for the specified key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.contains_key::<A, Q>(k)
+    }
+    #[inline]
+    /// Check if the map contains a value for the default key.
+    pub fn contains_default<A: 'static + ?Sized>(&self) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.map.contains_default::<A>()
+    }
+    #[inline]
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.get::<A, Q>(k)
+    }
+    #[inline]
+    /// Returns a reference to the value corresponding to the default key.
+    pub fn get_default<A: 'static + ?Sized>(&self) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.map.get_default::<A>()
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.get_mut::<A, Q>(k)
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the default key.
+    pub fn get_default_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.map.get_default_mut::<A>()
+    }
+    /// Inserts an entry into the map, allocating it in the arena if it is not stored inline.
+    ///
+    /// If the map did not have this key present, [`None`] is returned.
+    ///
+    /// Otherwise, the entry is fully replaced and `Some(old)` where `old` is the old entry is
+    /// returned. The new entry then takes the place of the old one, without allocating.
+    pub fn insert<A: 'static + ?Sized, P>(&mut self, entry: P) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let arena = &self.arena;
+        // The arena is only reset or dropped after the entries of the map are dropped
+        self.map.insert_with(entry.into(), |hash, entry| unsafe {
+            ErasedEntry::new_in::<A>(hash, entry, arena)
+        })
+    }
+    /// Removes a key from the map, returning the entry at the key if the key
+    /// was previously in the map. Its memory is only freed when clearing the map.
+    pub fn remove_entry<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.remove_entry::<A, Q>(k)
+    }
+    #[inline]
+    /// Removes the default key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    pub fn remove_default<A: 'static + ?Sized>(&mut self) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.map.remove_default::<A>()
+    }
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    pub fn remove<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.map.remove::<A, Q>(k)
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher + Default, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Default for ArenaMap<E, S, I, N>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> Debug
+    for ArenaMap<E, S, I, N>
+where
+    Map<E, S, I, N>: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.map.fmt(f)
+    }
+}
@@ -0,0 +1,679 @@
+//! A fixed-capacity [`ArrayMap`], which never allocates after construction.
+//!
+//! Entries live in an array of `N` slots stored directly in the map, and are looked up by a
+//! linear scan over the cached hashes. Every entry is stored inline in its slot, entries too
+//! large for that are rejected at compile time instead of being boxed up. Storage types that
+//! can't store entries inline at all are rejected with a panic on insertion, see [`ArrayMap`].
+use core::borrow::Borrow;
+use core::fmt::{Debug, Display, Formatter};
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+use crate::storage::{can_coerce, Storage};
+use crate::{
+    equivalent_key, hash_def_key, unreachable_internal_invariant, CreateEntry, DebugEntry,
+    DefaultHashBuilder, DynStorage, EntryAt, EntryFamily, ErasedEntry, ErasedEntryMut, HashEntry,
+    HashableAny, InnerEntry, KeyAt, SomeKey, SomeValue, ValueAt, DEFAULT_INLINE_WORDS,
+};
+
+// Fails to compile when instantiated for an entry that would have to be boxed up.
+struct AssertInline<T, I: ?Sized, const W: usize>(PhantomData<fn() -> (T, *const I)>);
+
+impl<T, I: ?Sized, const W: usize> AssertInline<T, I, W> {
+    const OK: () = assert!(
+        Storage::<I, W>::fits_inline::<T>(),
+        "the entry does not fit inline into a slot of the ArrayMap, increase `W`"
+    );
+}
+
+/// The error returned when inserting into an [`ArrayMap`] without any free slot left.
+///
+/// Contains whatever was rejected, i.e. the entry or the key that was looked up.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FullError<T>(pub T);
+
+impl<T> FullError<T> {
+    #[inline]
+    /// Take back whatever was rejected.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Debug for FullError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FullError").finish_non_exhaustive()
+    }
+}
+
+impl<T> Display for FullError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str("no free slot left in the map")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> std::error::Error for FullError<T> {}
+
+/// A map with room for at most `N` entries, which never allocates.
+///
+/// All entries are stored inline in one of the `N` slots of the map, each taking up `W` words,
+/// so that inserting and removing entries does not touch the allocator. This makes the map
+/// suitable for use on real-time threads, where allocating is not an option. Inserting an entry
+/// which doesn't fit into `W` words, or is aligned more than a `usize`, fails to compile.
+///
+/// Lookups scan all occupied slots, comparing the cached hashes first, which is fast for the
+/// small number of entries this map is meant for.
+///
+/// # Panics
+///
+/// Inserting panics if the storage type `I` can not store entries inline, because its
+/// [`CreateEntry`] implementation does not provide [`CreateEntry::coerce_entry`].
+pub struct ArrayMap<
+    E: ?Sized,
+    const N: usize,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
+    const W: usize = DEFAULT_INLINE_WORDS,
+> {
+    hash_state: S,
+    // The first `len` slots are occupied, the rest is empty
+    slots: [Option<ErasedEntry<E, I, W>>; N],
+    len: usize,
+}
+
+impl<E: ?Sized, const N: usize, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const W: usize>
+    ArrayMap<E, N, S, I, W>
+{
+    #[inline]
+    /// Create a new, empty, [`ArrayMap`].
+    pub fn new() -> Self
+    where
+        S: Default,
+    {
+        Self::with_hasher(S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`ArrayMap`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self {
+            hash_state,
+            slots: core::array::from_fn(|_| None),
+            len: 0,
+        }
+    }
+    #[inline]
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hash_state
+    }
+    #[inline]
+    /// The number of entries the map can hold, i.e. `N`.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+    #[inline]
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    /// Check if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    #[inline]
+    /// Check if all slots of the map are occupied.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+    #[inline]
+    /// Clear the map, dropping all entries.
+    pub fn clear(&mut self) {
+        for slot in &mut self.slots[..self.len] {
+            *slot = None;
+        }
+        self.len = 0;
+    }
+    #[inline]
+    /// An iterator visiting all entries in arbitrary order.
+    pub fn iter(&self) -> impl '_ + Iterator<Item = &'_ ErasedEntry<E, I, W>> {
+        self.slots[..self.len].iter().flatten()
+    }
+    #[inline]
+    /// An iterator visiting all entries in arbitrary order, with mutable access.
+    pub fn iter_mut(&mut self) -> impl '_ + Iterator<Item = ErasedEntryMut<'_, E, I, W>> {
+        self.slots[..self.len].iter_mut().flatten().map(ErasedEntryMut::new)
+    }
+    #[inline]
+    fn slot(&self, index: usize) -> &ErasedEntry<E, I, W> {
+        match &self.slots[index] {
+            Some(entry) => entry,
+            None => unreachable_internal_invariant("the first `len` slots are occupied"),
+        }
+    }
+    #[inline]
+    fn slot_mut(&mut self, index: usize) -> &mut ErasedEntry<E, I, W> {
+        match &mut self.slots[index] {
+            Some(entry) => entry,
+            None => unreachable_internal_invariant("the first `len` slots are occupied"),
+        }
+    }
+    // Moves the last occupied slot into the place of the removed one
+    #[inline]
+    fn take_slot(&mut self, index: usize) -> ErasedEntry<E, I, W> {
+        self.len -= 1;
+        self.slots.swap(index, self.len);
+        match self.slots[self.len].take() {
+            Some(entry) => entry,
+            None => unreachable_internal_invariant("the first `len` slots are occupied"),
+        }
+    }
+}
+
+impl<
+        E: 'static + ?Sized,
+        const N: usize,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const W: usize,
+    > ArrayMap<E, N, S, I, W>
+{
+    #[inline]
+    fn find<A: 'static + ?Sized, Q: ?Sized>(&self, hash: u64, key: &Q) -> Option<usize>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let mut eq = equivalent_key::<A, E, Q, I, W>(key);
+        self.slots[..self.len].iter().position(|slot| match slot {
+            Some(entry) => entry.hash == hash && eq(entry),
+            None => false,
+        })
+    }
+    #[inline]
+    fn find_key<A: 'static + ?Sized, Q: ?Sized>(&self, key: &Q) -> Option<usize>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = hash_def_key::<_, A, E, S>(&self.hash_state, key);
+        self.find::<A, Q>(hash, key)
+    }
+    #[inline]
+    fn inner<A: 'static + ?Sized>(&self, index: usize) -> &InnerEntry<E, A>
+    where
+        E: EntryFamily<A>,
+    {
+        match self.slot(index).downcast_ref() {
+            Some(inner) => inner,
+            None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        }
+    }
+    #[inline]
+    fn inner_mut<A: 'static + ?Sized>(&mut self, index: usize) -> &mut InnerEntry<E, A>
+    where
+        E: EntryFamily<A>,
+    {
+        match self.slot_mut(index).downcast_mut() {
+            Some(inner) => inner,
+            None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        }
+    }
+    #[inline]
+    fn take_inner<A: 'static + ?Sized>(&mut self, index: usize) -> EntryAt<E, A>
+    where
+        E: EntryFamily<A>,
+    {
+        match self.take_slot(index).downcast() {
+            Some(inner) => inner.entry,
+            None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        }
+    }
+    // The caller checks that there is a free slot left
+    #[inline]
+    fn push<A: 'static + ?Sized>(&mut self, hash: u64, entry: EntryAt<E, A>) -> usize
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+    {
+        let () = AssertInline::<InnerEntry<E, A>, I, W>::OK;
+        // Can't be checked at compile time, `coerce_entry` is not a const fn
+        assert!(
+            can_coerce::<A, E, I>(),
+            "the storage type of the ArrayMap does not implement `CreateEntry::coerce_entry`"
+        );
+        let index = self.len;
+        self.slots[index] = Some(ErasedEntry::new(hash, entry));
+        self.len += 1;
+        index
+    }
+    #[inline]
+    #[allow(clippy::type_complexity)]
+    /// Lookup the entry at `key`.
+    ///
+    /// Fails if the key is not present and there is no free slot left, so that a vacant entry
+    /// can always be inserted into.
+    pub fn entry<A: 'static + ?Sized>(
+        &mut self,
+        key: KeyAt<E, A>,
+    ) -> Result<Entry<'_, A, E, N, S, I, W>, FullError<KeyAt<E, A>>>
+    where
+        E: EntryFamily<A>,
+    {
+        let hash = hash_def_key::<_, A, E, S>(&self.hash_state, &key);
+        match self.find::<A, _>(hash, &key) {
+            Some(index) => Ok(Entry::Occupied(OccupiedEntry {
+                index,
+                table: self,
+                _marker: PhantomData,
+            })),
+            None if self.is_full() => Err(FullError(key)),
+            None => Ok(Entry::Vacant(VacantEntry {
+                hash,
+                key,
+                table: self,
+            })),
+        }
+    }
+    #[inline]
+    #[allow(clippy::type_complexity)]
+    /// Lookup the entry at the default key, see [`ArrayMap::entry`].
+    pub fn entry_default<A: 'static + ?Sized>(
+        &mut self,
+    ) -> Result<Entry<'_, A, E, N, S, I, W>, FullError<KeyAt<E, A>>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.entry(Default::default())
+    }
+    #[inline]
+    /// Check if the map contains a value for the specified key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.find_key::<A, Q>(k).is_some()
+    }
+    #[inline]
+    /// Check if the map contains a value for the default key.
+    pub fn contains_default<A: 'static + ?Sized>(&self) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.contains_key::<A, _>(&Default::default())
+    }
+    #[inline]
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.find_key::<A, Q>(k) {
+            Some(index) => Some(self.inner::<A>(index)),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Returns a reference to the value corresponding to the default key.
+    pub fn get_default<A: 'static + ?Sized>(&self) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get(&Default::default())
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.find_key::<A, Q>(k) {
+            Some(index) => Some(self.inner_mut::<A>(index).value_mut()),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the default key.
+    pub fn get_default_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get_mut::<A, _>(&Default::default())
+    }
+    /// Tries to insert an entry into the map.
+    ///
+    /// If the map did not have this key present, `Ok(None)` is returned. Otherwise, the entry is
+    /// fully replaced and `Ok(Some(old))` where `old` is the old entry is returned.
+    ///
+    /// If the key is not present and there is no free slot left, the entry is returned in a
+    /// [`FullError`] instead.
+    #[allow(clippy::type_complexity)]
+    pub fn try_insert<A: 'static + ?Sized, P>(
+        &mut self,
+        entry: P,
+    ) -> Result<Option<EntryAt<E, A>>, FullError<EntryAt<E, A>>>
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let entry = entry.into();
+        let key = entry.split_ref().0;
+        let hash = hash_def_key::<_, A, E, S>(&self.hash_state, key);
+        match self.find::<A, _>(hash, key) {
+            Some(index) => Ok(Some(core::mem::replace(
+                &mut self.inner_mut::<A>(index).entry,
+                entry,
+            ))),
+            None if self.is_full() => Err(FullError(entry)),
+            None => {
+                let _ = self.push::<A>(hash, entry);
+                Ok(None)
+            }
+        }
+    }
+    /// Removes a key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    #[allow(clippy::manual_map)]
+    pub fn remove_entry<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.find_key::<A, Q>(k) {
+            Some(index) => Some(self.take_inner::<A>(index)),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Removes the default key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    pub fn remove_default<A: 'static + ?Sized>(&mut self) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.remove_entry::<A, _>(&Default::default())
+    }
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    #[allow(clippy::manual_map)]
+    pub fn remove<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.remove_entry::<A, Q>(k) {
+            Some(v) => Some(v.into_parts().1),
+            None => None,
+        }
+    }
+}
+
+/// An occupied entry in an [`ArrayMap`], see [`ArrayMap::entry`].
+pub struct OccupiedEntry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    const N: usize,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const W: usize = DEFAULT_INLINE_WORDS,
+> {
+    index: usize,
+    table: &'a mut ArrayMap<E, N, S, I, W>,
+    _marker: PhantomData<fn() -> A>,
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        const N: usize,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const W: usize,
+    > OccupiedEntry<'a, A, E, N, S, I, W>
+{
+    #[inline]
+    /// Get the key of the entry stored in the map
+    pub fn key(&self) -> &KeyAt<E, A> {
+        self.table.inner::<A>(self.index).key()
+    }
+    #[inline]
+    /// Get the pair of (key, value) found in the map for this entry.
+    pub fn hash_entry(&self) -> &EntryAt<E, A> {
+        self.table.inner::<A>(self.index)
+    }
+    #[inline]
+    /// Get the value found in the map for this entry
+    pub fn get(&self) -> &ValueAt<E, A> {
+        self.table.inner::<A>(self.index).value()
+    }
+    #[inline]
+    /// Get the value found in the map for this entry
+    pub fn get_mut(&mut self) -> &mut ValueAt<E, A> {
+        self.table.inner_mut::<A>(self.index).value_mut()
+    }
+    #[inline]
+    /// Converts the entry into a mutable reference to the value in the map that is bound
+    /// to the lifetime of the map.
+    pub fn into_mut(self) -> &'a mut ValueAt<E, A> {
+        self.table.inner_mut::<A>(self.index).value_mut()
+    }
+    #[inline]
+    /// Replace the value found in the map for this entry and return the old value
+    pub fn insert(&mut self, value: ValueAt<E, A>) -> ValueAt<E, A> {
+        core::mem::replace(self.get_mut(), value)
+    }
+    #[inline]
+    /// Remove the entry from the map and return its value
+    pub fn remove(self) -> ValueAt<E, A> {
+        self.remove_entry().into_parts().1
+    }
+    #[inline]
+    /// Remove and return the entry from the map.
+    pub fn remove_entry(self) -> EntryAt<E, A> {
+        self.table.take_inner::<A>(self.index)
+    }
+}
+
+/// A vacant entry in an [`ArrayMap`], for which a free slot is available.
+pub struct VacantEntry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    const N: usize,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const W: usize = DEFAULT_INLINE_WORDS,
+> {
+    hash: u64,
+    key: KeyAt<E, A>,
+    table: &'a mut ArrayMap<E, N, S, I, W>,
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        const N: usize,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const W: usize,
+    > VacantEntry<'a, A, E, N, S, I, W>
+{
+    #[inline]
+    /// Get the key that was used during lookup
+    pub fn key(&self) -> &KeyAt<E, A> {
+        &self.key
+    }
+    #[inline]
+    /// Take ownership of the key that was used during lookup
+    pub fn into_key(self) -> KeyAt<E, A> {
+        self.key
+    }
+    /// Insert an entry, taking ownership of the already supplied key.
+    ///
+    /// Returns mutable access to inserted value.
+    pub fn insert(self, value: impl FnOnce(KeyAt<E, A>) -> EntryAt<E, A>) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+    {
+        let index = self.table.push::<A>(self.hash, value(self.key));
+        self.table.inner_mut::<A>(index).value_mut()
+    }
+    /// Insert an entry, taking ownership of the already supplied key.
+    ///
+    /// Returns an [`OccupiedEntry`] pointing at the inserted entry.
+    pub fn insert_entry(
+        self,
+        value: impl FnOnce(KeyAt<E, A>) -> EntryAt<E, A>,
+    ) -> OccupiedEntry<'a, A, E, N, S, I, W>
+    where
+        I: CreateEntry<A, E>,
+    {
+        let index = self.table.push::<A>(self.hash, value(self.key));
+        OccupiedEntry {
+            index,
+            table: self.table,
+            _marker: PhantomData,
+        }
+    }
+    #[inline]
+    /// Insert an entry, by converting the key into an entry.
+    ///
+    /// Returns mutable access to inserted value.
+    pub fn insert_into(self) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+        EntryAt<E, A>: From<KeyAt<E, A>>,
+    {
+        self.insert(|k| k.into())
+    }
+}
+
+/// An entry in an [`ArrayMap`], see [`ArrayMap::entry`].
+pub enum Entry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    const N: usize,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const W: usize = DEFAULT_INLINE_WORDS,
+> {
+    #[allow(missing_docs)]
+    Occupied(OccupiedEntry<'a, A, E, N, S, I, W>),
+    #[allow(missing_docs)]
+    Vacant(VacantEntry<'a, A, E, N, S, I, W>),
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        const N: usize,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const W: usize,
+    > Entry<'a, A, E, N, S, I, W>
+{
+    #[inline]
+    /// Get the key of this entry, either the one stored in the map or the one used during lookup.
+    pub fn key(&self) -> &KeyAt<E, A> {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting `default` if empty.
+    ///
+    /// Returns mutable access to the value in the entry.
+    pub fn or_insert(self, default: ValueAt<E, A>) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+    {
+        self.or_insert_with(|| default)
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the result of `default` if empty.
+    ///
+    /// Returns mutable access to the value in the entry.
+    pub fn or_insert_with(self, default: impl FnOnce() -> ValueAt<E, A>) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(|k| HashEntry::from_parts(k, default())),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the default value if empty.
+    ///
+    /// Returns mutable access to the value in the entry.
+    pub fn or_default(self) -> &'a mut ValueAt<E, A>
+    where
+        I: CreateEntry<A, E>,
+        ValueAt<E, A>: Default,
+    {
+        self.or_insert_with(Default::default)
+    }
+    #[inline]
+    /// Provides in-place mutable access to an occupied entry before any potential inserts into the map.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut ValueAt<E, A>)) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
+
+impl<
+        E: ?Sized,
+        const N: usize,
+        S: BuildHasher + Default,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const W: usize,
+    > Default for ArrayMap<E, N, S, I, W>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<
+        E: ?Sized,
+        const N: usize,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher> + DebugEntry,
+        const W: usize,
+    > Debug for ArrayMap<E, N, S, I, W>
+{
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt.debug_map()
+            .entries(self.iter().map(|e| (SomeKey(e), SomeValue(e))))
+            .finish()
+    }
+}
@@ -0,0 +1,684 @@
+//! A sharded [`ConcurrentMap`] that can be shared between threads without an external lock.
+//!
+//! Entries are distributed over a fixed number of shards, each protected by its own
+//! [`RwLock`]. The shard of an entry is chosen by the same hash that is used to look it up
+//! in the shard, so operations on different shards never contend.
+//!
+//! Locks are held by the guards returned from lookups, see [`Ref`], [`RefMut`] and [`Entry`].
+//! Holding a guard while accessing the same map again from the same thread can deadlock.
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::PoisonError;
+use hashbrown::raw::{Bucket, RawTable};
+#[cfg(loom)]
+use loom::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(not(loom))]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::variants::SendSyncHashableAny;
+use crate::{
+    equivalent_key, hash_def_key, make_hasher, unreachable_internal_invariant, CreateEntry,
+    DefaultHashBuilder, DEFAULT_INLINE_WORDS, EntryAt, EntryFamily, ErasedEntry, HashEntry, HashableAny, InnerEntry,
+    KeyAt, NonOwningPhantomPointer, RefAny, ValueAt,
+};
+
+type Shard<E, I, const N: usize> = RawTable<ErasedEntry<E, I, N>>;
+type SendSyncDynStorage<S> = dyn SendSyncHashableAny<<S as BuildHasher>::Hasher>;
+
+/// A concurrent hash map, sharded into several [`RwLock`]-protected tables.
+///
+/// The storage type defaults to [`SendSyncHashableAny`], so that the map is [`Send`] and
+/// [`Sync`] and can be shared between threads, e.g. in an [`Arc`](std::sync::Arc).
+///
+/// Lock poisoning is ignored. A panic while a shard is locked can not leave the table itself
+/// in an inconsistent state, although a value that was being modified might be.
+pub struct ConcurrentMap<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = SendSyncDynStorage<S>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    // Amount to shift the hash by to get the index of the shard, see `shard_index`
+    shift: u32,
+    shards: Box<[RwLock<Shard<E, I, N>>]>,
+    hash_state: S,
+}
+
+#[inline]
+fn default_shard_amount() -> usize {
+    let parallelism = match std::thread::available_parallelism() {
+        Ok(n) => n.get(),
+        Err(_) => 1,
+    };
+    (parallelism * 4).next_power_of_two()
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    ConcurrentMap<E, S, I, N>
+{
+    #[inline]
+    /// Create a new, empty, [`ConcurrentMap`], with a shard amount depending on the available
+    /// parallelism.
+    pub fn new() -> Self
+    where
+        S: Default,
+    {
+        Self::with_hasher(S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`ConcurrentMap`] with the given amount of shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shard_amount` is not a power of two.
+    pub fn with_shard_amount(shard_amount: usize) -> Self
+    where
+        S: Default,
+    {
+        Self::with_shard_amount_and_hasher(shard_amount, S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`ConcurrentMap`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self::with_shard_amount_and_hasher(default_shard_amount(), hash_state)
+    }
+    /// Create a new, empty, [`ConcurrentMap`] with the given amount of shards, which will use
+    /// the given hash builder to hash keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shard_amount` is not a power of two.
+    pub fn with_shard_amount_and_hasher(shard_amount: usize, hash_state: S) -> Self {
+        assert!(
+            shard_amount.is_power_of_two(),
+            "shard amount must be a power of two"
+        );
+        Self {
+            shift: usize::BITS - shard_amount.trailing_zeros(),
+            shards: (0..shard_amount)
+                .map(|_| RwLock::new(RawTable::new()))
+                .collect(),
+            hash_state,
+        }
+    }
+    #[inline]
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hash_state
+    }
+    #[inline]
+    /// The amount of shards the entries are distributed over.
+    pub fn shard_amount(&self) -> usize {
+        self.shards.len()
+    }
+    /// The number of entries in the map.
+    ///
+    /// The shards are locked one after another, so the result might be outdated if other
+    /// threads modify the map concurrently.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|i| self.read_shard(i).len())
+            .sum()
+    }
+    /// Check if the map contains no entries, see [`ConcurrentMap::len`].
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|i| self.read_shard(i).len() == 0)
+    }
+    /// Clear the map, removing all entries.
+    ///
+    /// The shards are cleared one after another.
+    pub fn clear(&self) {
+        for i in 0..self.shards.len() {
+            self.write_shard(i).clear();
+        }
+    }
+    #[inline]
+    fn shard_index(&self, hash: u64) -> usize {
+        // The top 7 bits are used by the table of the shard for its control bytes, and the low
+        // bits choose the bucket. Take the bits right below the top 7 for the shard.
+        // With a single shard, the shift is the full width and everything maps to shard 0.
+        ((hash as usize) << 7).checked_shr(self.shift).unwrap_or(0)
+    }
+    #[inline]
+    fn read_shard(&self, idx: usize) -> RwLockReadGuard<'_, Shard<E, I, N>> {
+        self.shards[idx]
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    #[inline]
+    fn write_shard(&self, idx: usize) -> RwLockWriteGuard<'_, Shard<E, I, N>> {
+        self.shards[idx]
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    ConcurrentMap<E, S, I, N>
+{
+    #[inline]
+    fn hash_key<A: 'static + ?Sized, Q: ?Sized + Hash>(&self, key: &Q) -> u64
+    where
+        E: EntryFamily<A>,
+    {
+        hash_def_key::<_, A, E, S>(&self.hash_state, key)
+    }
+    /// Check if the map contains an entry for the given key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, Q>(k);
+        let shard = self.read_shard(self.shard_index(hash));
+        shard.find(hash, equivalent_key::<A, E, Q, I, N>(k)).is_some()
+    }
+    /// Get the entry stored for the given key.
+    ///
+    /// The shard containing the entry stays read-locked while the returned guard is alive.
+    pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<Ref<'_, A, E, I, N>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, Q>(k);
+        let guard = self.read_shard(self.shard_index(hash));
+        let raw = guard.get(hash, equivalent_key(k))?;
+        let entry = match raw.downcast_ref::<A>() {
+            Some(inner) => NonNull::from(inner),
+            None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        };
+        Some(Ref {
+            _guard: guard,
+            entry,
+        })
+    }
+    #[inline]
+    /// Get the entry stored for the default key, see [`ConcurrentMap::get`].
+    pub fn get_default<A: 'static + ?Sized>(&self) -> Option<Ref<'_, A, E, I, N>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get::<A, _>(&KeyAt::<E, A>::default())
+    }
+    /// Get mutable access to the value stored for the given key.
+    ///
+    /// The shard containing the entry stays write-locked while the returned guard is alive.
+    pub fn get_mut<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<RefMut<'_, A, E, I, N>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, Q>(k);
+        let mut guard = self.write_shard(self.shard_index(hash));
+        let raw = guard.get_mut(hash, equivalent_key(k))?;
+        let entry = match raw.downcast_mut::<A>() {
+            Some(inner) => NonNull::from(inner),
+            None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        };
+        Some(RefMut {
+            _guard: guard,
+            entry,
+        })
+    }
+    /// Inserts an entry into the map.
+    ///
+    /// If the map did not have this key present, [`None`] is returned.
+    ///
+    /// Otherwise, the entry is fully replaced and `Some(old)` where `old` is the old entry is returned.
+    pub fn insert<A: 'static + ?Sized, P>(&self, entry: P) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let entry = entry.into();
+        let key = entry.split_ref().0;
+        let hash = self.hash_key::<A, _>(key);
+        let mut shard = self.write_shard(self.shard_index(hash));
+        if let Some(existing) = shard.get_mut(hash, equivalent_key(key)) {
+            match existing.downcast_mut::<A>() {
+                Some(existing) => Some(std::mem::replace(&mut existing.entry, entry)),
+                None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+            }
+        } else {
+            let raw_entry = ErasedEntry::<E, I, N>::new(hash, entry);
+            let hashfn = make_hasher(&self.hash_state);
+            let _ = shard.insert(hash, raw_entry, hashfn);
+            None
+        }
+    }
+    /// Removes a key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    pub fn remove_entry<A: 'static + ?Sized, Q: ?Sized>(&self, key: &Q) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, Q>(key);
+        let mut shard = self.write_shard(self.shard_index(hash));
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match shard.remove_entry(hash, equivalent_key(key)) {
+            Some(v) => match v.downcast() {
+                Some(raw) => Some(raw.entry),
+                None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+            },
+            None => None,
+        }
+    }
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    #[allow(clippy::manual_map)]
+    pub fn remove<A: 'static + ?Sized, Q: ?Sized>(&self, key: &Q) -> Option<ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.remove_entry::<A, Q>(key) {
+            Some(entry) => Some(entry.into_parts().1),
+            None => None,
+        }
+    }
+    /// Gets the given key's corresponding entry in the map for in-place manipulation.
+    ///
+    /// The shard the key belongs to stays write-locked while the entry is alive.
+    pub fn entry<A: 'static + ?Sized>(&self, key: KeyAt<E, A>) -> Entry<'_, A, E, S, I, N>
+    where
+        E: EntryFamily<A>,
+    {
+        let hash = self.hash_key::<A, _>(&key);
+        let shard = self.write_shard(self.shard_index(hash));
+        match shard.find(hash, equivalent_key(&key)) {
+            Some(elem) => Entry::Occupied(OccupiedEntry {
+                shard,
+                elem,
+                _marker: PhantomData,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                shard,
+                hash,
+                key,
+                map: self,
+            }),
+        }
+    }
+    #[inline]
+    /// Get mutable access to the value stored for the given key, inserting the result of
+    /// `default` first if the key is not present.
+    ///
+    /// Shorthand for `self.entry(key).or_insert_with(default)`.
+    pub fn get_or_insert_with<A: 'static + ?Sized>(
+        &self,
+        key: KeyAt<E, A>,
+        default: impl FnOnce() -> ValueAt<E, A>,
+    ) -> RefMut<'_, A, E, I, N>
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+    {
+        self.entry(key).or_insert_with(default)
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher + Default, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Default for ConcurrentMap<E, S, I, N>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A read guard for an entry of a [`ConcurrentMap`], see [`ConcurrentMap::get`].
+///
+/// Dereferences to the pair of (key, value) stored in the map.
+pub struct Ref<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    I: ?Sized,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    _guard: RwLockReadGuard<'a, Shard<E, I, N>>,
+    // Points into the storage of the entry. The guard keeps the shard from being modified,
+    // so the entry is neither moved nor changed while it is alive.
+    entry: NonNull<InnerEntry<E, A>>,
+}
+
+impl<'a, A: ?Sized, E: ?Sized + EntryFamily<A>, I: ?Sized, const N: usize> Ref<'a, A, E, I, N> {
+    #[inline]
+    /// Get the key of the entry
+    pub fn key(&self) -> &KeyAt<E, A> {
+        self.split_ref().0
+    }
+    #[inline]
+    /// Get the value of the entry
+    pub fn value(&self) -> &ValueAt<E, A> {
+        self.split_ref().1
+    }
+}
+
+impl<'a, A: ?Sized, E: ?Sized + EntryFamily<A>, I: ?Sized, const N: usize> Deref
+    for Ref<'a, A, E, I, N>
+{
+    type Target = EntryAt<E, A>;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        // the shard is read-locked for as long as the guard lives
+        unsafe { &self.entry.as_ref().entry }
+    }
+}
+
+/// A write guard for an entry of a [`ConcurrentMap`], see [`ConcurrentMap::get_mut`].
+///
+/// Dereferences to the value stored in the map.
+pub struct RefMut<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    I: ?Sized,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    _guard: RwLockWriteGuard<'a, Shard<E, I, N>>,
+    // Points into the storage of the entry. The guard keeps the shard from being modified
+    // elsewhere, so the entry is neither moved nor aliased while it is alive.
+    entry: NonNull<InnerEntry<E, A>>,
+}
+
+impl<'a, A: ?Sized, E: ?Sized + EntryFamily<A>, I: ?Sized, const N: usize> RefMut<'a, A, E, I, N> {
+    #[inline]
+    /// Get the key of the entry
+    pub fn key(&self) -> &KeyAt<E, A> {
+        // the shard is write-locked for as long as the guard lives
+        unsafe { self.entry.as_ref().key() }
+    }
+    #[inline]
+    /// Get the pair of (key, value) stored in the map.
+    pub fn hash_entry(&self) -> &EntryAt<E, A> {
+        // the shard is write-locked for as long as the guard lives
+        unsafe { &self.entry.as_ref().entry }
+    }
+}
+
+impl<'a, A: ?Sized, E: ?Sized + EntryFamily<A>, I: ?Sized, const N: usize> Deref
+    for RefMut<'a, A, E, I, N>
+{
+    type Target = ValueAt<E, A>;
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        // the shard is write-locked for as long as the guard lives
+        unsafe { self.entry.as_ref().value() }
+    }
+}
+
+impl<'a, A: ?Sized, E: ?Sized + EntryFamily<A>, I: ?Sized, const N: usize> DerefMut
+    for RefMut<'a, A, E, I, N>
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // the shard is write-locked for as long as the guard lives
+        unsafe { self.entry.as_mut().value_mut() }
+    }
+}
+
+/// An occupied entry in a [`ConcurrentMap`], holding the write lock of its shard.
+pub struct OccupiedEntry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    I: ?Sized,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    shard: RwLockWriteGuard<'a, Shard<E, I, N>>,
+    elem: Bucket<ErasedEntry<E, I, N>>,
+    _marker: NonOwningPhantomPointer<A>,
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        I: ?Sized + RefAny,
+        const N: usize,
+    > OccupiedEntry<'a, A, E, I, N>
+{
+    #[inline]
+    fn entry(&self) -> &InnerEntry<E, A> {
+        // holding the lock of the shard, didn't rehash or reallocate
+        let inner_ref = unsafe { self.elem.as_ref() };
+        match inner_ref.downcast_ref() {
+            Some(r) => r,
+            // invariant of how we obtained the entry
+            None => unreachable_internal_invariant(
+                "the entry is constructed pointing only at correct types",
+            ),
+        }
+    }
+    #[inline]
+    fn entry_mut(&mut self) -> &mut InnerEntry<E, A> {
+        // holding the lock of the shard, didn't rehash or reallocate
+        let inner_ref = unsafe { self.elem.as_mut() };
+        match inner_ref.downcast_mut() {
+            Some(r) => r,
+            // invariant of how we obtained the entry
+            None => unreachable_internal_invariant(
+                "the entry is constructed pointing only at correct types",
+            ),
+        }
+    }
+    #[inline]
+    /// Get the key of the entry stored in the map
+    pub fn key(&self) -> &KeyAt<E, A> {
+        self.entry().key()
+    }
+    #[inline]
+    /// Get the value found in the map for this entry
+    pub fn get(&self) -> &ValueAt<E, A> {
+        self.entry().value()
+    }
+    #[inline]
+    /// Get the value found in the map for this entry
+    pub fn get_mut(&mut self) -> &mut ValueAt<E, A> {
+        self.entry_mut().value_mut()
+    }
+    #[inline]
+    /// Replace the value found in the map for this entry and return the old value
+    pub fn insert(&mut self, value: ValueAt<E, A>) -> ValueAt<E, A> {
+        std::mem::replace(self.get_mut(), value)
+    }
+    #[inline]
+    /// Converts the entry into a guard for the value, keeping the shard locked.
+    pub fn into_ref(mut self) -> RefMut<'a, A, E, I, N> {
+        let entry = NonNull::from(self.entry_mut());
+        RefMut {
+            _guard: self.shard,
+            entry,
+        }
+    }
+    #[inline]
+    /// Remove the entry from the map and return its value
+    pub fn remove(self) -> ValueAt<E, A>
+    where
+        EntryAt<E, A>: Sized,
+    {
+        self.remove_entry().into_parts().1
+    }
+    /// Remove and return the entry from the map.
+    ///
+    /// Use [`HashEntry::into_parts`] to split it into the key and the value.
+    pub fn remove_entry(mut self) -> EntryAt<E, A> {
+        // holding the lock of the shard, didn't rehash or reallocate
+        let raw = unsafe { self.shard.remove(self.elem) };
+        match raw.downcast() {
+            Some(r) => r,
+            // invariant of how we obtained the entry
+            None => unreachable_internal_invariant(
+                "the entry is constructed pointing only at correct types",
+            ),
+        }
+        .entry
+    }
+}
+
+/// A vacant entry in a [`ConcurrentMap`], holding the write lock of its shard.
+pub struct VacantEntry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    shard: RwLockWriteGuard<'a, Shard<E, I, N>>,
+    hash: u64,
+    key: KeyAt<E, A>,
+    map: &'a ConcurrentMap<E, S, I, N>,
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > VacantEntry<'a, A, E, S, I, N>
+{
+    #[inline]
+    /// Get the key that was used during lookup
+    pub fn key(&self) -> &KeyAt<E, A> {
+        &self.key
+    }
+    #[inline]
+    /// Take ownership of the key that was used during lookup
+    pub fn into_key(self) -> KeyAt<E, A> {
+        self.key
+    }
+    /// Insert an entry, taking ownership of the already supplied key.
+    ///
+    /// Returns a guard for the inserted value, keeping the shard locked.
+    pub fn insert(
+        mut self,
+        value: impl FnOnce(KeyAt<E, A>) -> EntryAt<E, A>,
+    ) -> RefMut<'a, A, E, I, N>
+    where
+        I: CreateEntry<A, E>,
+    {
+        let raw_entry = ErasedEntry::new(self.hash, value(self.key));
+        let hashfn = make_hasher(&self.map.hash_state);
+        let ins_entry = self.shard.insert_entry(self.hash, raw_entry, hashfn);
+        let entry = match ins_entry.downcast_mut() {
+            Some(m) => NonNull::from(m),
+            None => unreachable_internal_invariant("inserted type is correct"),
+        };
+        RefMut {
+            _guard: self.shard,
+            entry,
+        }
+    }
+}
+
+/// An entry in a [`ConcurrentMap`], see [`ConcurrentMap::entry`].
+pub enum Entry<
+    'a,
+    A: ?Sized,
+    E: ?Sized + EntryFamily<A>,
+    S: BuildHasher,
+    I: ?Sized + HashableAny<S::Hasher>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    #[allow(missing_docs)]
+    Occupied(OccupiedEntry<'a, A, E, I, N>),
+    #[allow(missing_docs)]
+    Vacant(VacantEntry<'a, A, E, S, I, N>),
+}
+
+impl<
+        'a,
+        A: 'static + ?Sized,
+        E: 'static + ?Sized + EntryFamily<A>,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher>,
+        const N: usize,
+    > Entry<'a, A, E, S, I, N>
+{
+    #[inline]
+    /// Get the key of this entry, either the one stored in the map or the one used during lookup.
+    pub fn key(&self) -> &KeyAt<E, A> {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting `default` if empty.
+    ///
+    /// Returns a guard for the value in the entry.
+    pub fn or_insert(self, default: ValueAt<E, A>) -> RefMut<'a, A, E, I, N>
+    where
+        I: CreateEntry<A, E>,
+    {
+        self.or_insert_with(|| default)
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the result of `default` if empty.
+    ///
+    /// Returns a guard for the value in the entry.
+    pub fn or_insert_with(self, default: impl FnOnce() -> ValueAt<E, A>) -> RefMut<'a, A, E, I, N>
+    where
+        I: CreateEntry<A, E>,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_ref(),
+            Entry::Vacant(e) => e.insert(|k| HashEntry::from_parts(k, default())),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the result of `default` if empty.
+    /// The key of the entry is passed to `default`.
+    ///
+    /// Returns a guard for the value in the entry.
+    pub fn or_insert_with_key(
+        self,
+        default: impl FnOnce(&KeyAt<E, A>) -> ValueAt<E, A>,
+    ) -> RefMut<'a, A, E, I, N>
+    where
+        I: CreateEntry<A, E>,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_ref(),
+            Entry::Vacant(e) => e.insert(|k| {
+                let value = default(&k);
+                HashEntry::from_parts(k, value)
+            }),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the default value if empty.
+    ///
+    /// Returns a guard for the value in the entry.
+    pub fn or_default(self) -> RefMut<'a, A, E, I, N>
+    where
+        I: CreateEntry<A, E>,
+        ValueAt<E, A>: Default,
+    {
+        self.or_insert_with(Default::default)
+    }
+    #[inline]
+    /// Provides in-place mutable access to an occupied entry before any potential inserts into the map.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut ValueAt<E, A>)) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
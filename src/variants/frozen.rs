@@ -0,0 +1,411 @@
+//! A read-only [`FrozenMap`], built with [`Map::freeze`] and looked up with a perfect hash.
+//!
+//! The layout follows the "hash, displace and compress" scheme, as used by the `phf` crate:
+//! the hashes of all entries are sorted into buckets, and for each bucket a displacement is
+//! searched that moves all its hashes to slots not used by any other bucket. A lookup then
+//! computes the slot of a key directly, without probing.
+use core::borrow::Borrow;
+use core::fmt::{Debug, Formatter};
+use core::hash::{BuildHasher, Hash};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::{
+    equivalent_entry, equivalent_key, hash_def_entry, hash_def_key, unreachable_internal_invariant,
+    DebugEntry, DefaultHashBuilder, DynClone, DynEq, DynPartialEq, DynStorage, EntryAt,
+    EntryFamily, ErasedEntry, ErasedEntryMut, HashableAny, KeyAt, Map, SomeKey, SomeValue, ValueAt,
+    DEFAULT_INLINE_WORDS,
+};
+
+// Average number of slots per bucket. Larger buckets save space for the displacements,
+// but take longer to place.
+const LAMBDA: usize = 5;
+
+// Mixes the seed into the hash, so that a failed layout can be retried with a new seed.
+// This is a bijection, distinct hashes stay distinct.
+#[inline]
+fn mix(hash: u64, seed: u64) -> u64 {
+    let mut h = hash ^ seed;
+    h = (h ^ (h >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h = (h ^ (h >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+// Split a mixed hash into the bucket selector and the two parts of the displacement function
+#[inline]
+fn split(hash: u64) -> (u32, u32, u32) {
+    let g = (hash >> 32) as u32;
+    let f1 = hash as u32;
+    let f2 = (hash.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as u32;
+    (g, f1, f2)
+}
+
+#[inline]
+fn displace(f1: u32, f2: u32, (d1, d2): (u32, u32)) -> u32 {
+    f2.wrapping_add(f1.wrapping_mul(d1)).wrapping_add(d2)
+}
+
+// The perfect hash function over a set of distinct hashes
+struct Layout {
+    seed: u64,
+    disps: Box<[(u32, u32)]>,
+    // For each slot, the index into the hashes it was built from
+    slots: Box<[usize]>,
+}
+
+impl Layout {
+    // Try to place all hashes with the given seed, fails if a bucket can not be placed
+    fn try_build(hashes: &[u64], seed: u64) -> Option<Layout> {
+        let slot_len = hashes.len();
+        let buckets_len = (slot_len + LAMBDA - 1) / LAMBDA;
+        let mut buckets: Vec<Vec<usize>> = vec![Vec::new(); buckets_len];
+        for (idx, &hash) in hashes.iter().enumerate() {
+            let (g, _, _) = split(mix(hash, seed));
+            buckets[g as usize % buckets_len].push(idx);
+        }
+        // Place the largest buckets first, while there are still many free slots
+        let mut order: Vec<usize> = (0..buckets_len).collect();
+        order.sort_by_key(|&bucket| core::cmp::Reverse(buckets[bucket].len()));
+
+        let mut disps = vec![(0, 0); buckets_len].into_boxed_slice();
+        let mut slots: Vec<Option<usize>> = vec![None; slot_len];
+        // Marks the slots tried for the current displacement, to detect collisions within a bucket
+        let mut tried = vec![0u64; slot_len];
+        let mut generation = 0u64;
+        let mut placed = Vec::with_capacity(LAMBDA);
+        'buckets: for bucket in order {
+            let bucket_hashes = &buckets[bucket];
+            if bucket_hashes.is_empty() {
+                continue;
+            }
+            for d1 in 0..slot_len as u32 {
+                'disps: for d2 in 0..slot_len as u32 {
+                    generation += 1;
+                    placed.clear();
+                    for &idx in bucket_hashes {
+                        let (_, f1, f2) = split(mix(hashes[idx], seed));
+                        let slot = displace(f1, f2, (d1, d2)) as usize % slot_len;
+                        if slots[slot].is_some() || tried[slot] == generation {
+                            continue 'disps;
+                        }
+                        tried[slot] = generation;
+                        placed.push((slot, idx));
+                    }
+                    for &(slot, idx) in &placed {
+                        slots[slot] = Some(idx);
+                    }
+                    disps[bucket] = (d1, d2);
+                    continue 'buckets;
+                }
+            }
+            return None;
+        }
+        let slots = slots
+            .into_iter()
+            .map(|idx| match idx {
+                Some(idx) => idx,
+                None => unreachable_internal_invariant("every slot is filled by a hash"),
+            })
+            .collect();
+        Some(Layout { seed, disps, slots })
+    }
+
+    fn build(hashes: &[u64]) -> Layout {
+        // A layout for distinct hashes exists for almost all seeds, retrying rarely happens
+        let mut seed = 0u64;
+        loop {
+            if let Some(layout) = Self::try_build(hashes, seed) {
+                return layout;
+            }
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        }
+    }
+
+    #[inline]
+    fn slot(&self, hash: u64) -> usize {
+        let (g, f1, f2) = split(mix(hash, self.seed));
+        let disp = self.disps[g as usize % self.disps.len()];
+        displace(f1, f2, disp) as usize % self.slots.len()
+    }
+}
+
+/// A read-only map, built from a [`Map`] with [`Map::freeze`].
+///
+/// Lookups compute the position of an entry with a perfect hash function over the hashes of
+/// all stored entries, and compare only against the entries found there. No other entries are
+/// probed. Values can still be modified with [`FrozenMap::get_mut`], but entries can not be
+/// inserted or removed.
+pub struct FrozenMap<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    hash_state: S,
+    layout: Layout,
+    // The entries of slot `i` are `entries[starts[i]..starts[i + 1]]`, all with the same hash.
+    // There is more than one entry in a slot only if the full hashes collide.
+    starts: Box<[usize]>,
+    entries: Box<[ErasedEntry<E, I, N>]>,
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Map<E, S, I, N>
+{
+    /// Freeze the map into a [`FrozenMap`], which can only be read from, but is faster to look
+    /// up. Building the perfect hash takes time roughly proportional to the number of entries.
+    pub fn freeze(self) -> FrozenMap<E, S, I, N> {
+        let (hash_state, entries) = self.into_raw_parts();
+        let mut entries: Vec<_> = entries.collect();
+        entries.sort_unstable_by_key(|entry| entry.hash);
+        let mut hashes: Vec<u64> = entries.iter().map(|entry| entry.hash).collect();
+        hashes.dedup();
+        // Group starts in the sorted entries, for each distinct hash
+        let mut group_starts = Vec::with_capacity(hashes.len() + 1);
+        for (idx, entry) in entries.iter().enumerate() {
+            if idx == 0 || entries[idx - 1].hash != entry.hash {
+                group_starts.push(idx);
+            }
+        }
+        group_starts.push(entries.len());
+
+        let layout = Layout::build(&hashes);
+        // Move the groups into the order of their slots
+        let mut groups: Vec<Option<ErasedEntry<E, I, N>>> = entries.into_iter().map(Some).collect();
+        let mut starts = Vec::with_capacity(hashes.len() + 1);
+        let mut ordered = Vec::with_capacity(groups.len());
+        for &group in layout.slots.iter() {
+            starts.push(ordered.len());
+            for entry in &mut groups[group_starts[group]..group_starts[group + 1]] {
+                match entry.take() {
+                    Some(entry) => ordered.push(entry),
+                    None => unreachable_internal_invariant("each group is in exactly one slot"),
+                }
+            }
+        }
+        starts.push(ordered.len());
+        FrozenMap {
+            hash_state,
+            layout,
+            starts: starts.into_boxed_slice(),
+            entries: ordered.into_boxed_slice(),
+        }
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    FrozenMap<E, S, I, N>
+{
+    #[inline]
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hash_state
+    }
+    #[inline]
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    #[inline]
+    /// Check if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    #[inline]
+    /// Iterate over all entries of the map, in arbitrary order.
+    pub fn iter(&self) -> core::slice::Iter<'_, ErasedEntry<E, I, N>> {
+        self.entries.iter()
+    }
+    #[inline]
+    /// Iterate mutably over all entries of the map, in arbitrary order.
+    ///
+    /// Only the values can be modified, the keys and the layout of the map stay fixed.
+    pub fn iter_mut(&mut self) -> impl '_ + Iterator<Item = ErasedEntryMut<'_, E, I, N>> {
+        self.entries.iter_mut().map(ErasedEntryMut::new)
+    }
+    #[inline]
+    // The entries that could have the given hash
+    fn candidates(&self, hash: u64) -> core::ops::Range<usize> {
+        if self.entries.is_empty() {
+            return 0..0;
+        }
+        let slot = self.layout.slot(hash);
+        let range = self.starts[slot]..self.starts[slot + 1];
+        if self.entries[range.start].hash == hash {
+            range
+        } else {
+            0..0
+        }
+    }
+    /// Move all entries back into a [`Map`].
+    pub fn thaw(self) -> Map<E, S, I, N> {
+        let mut map = Map::with_capacity_and_hasher(self.entries.len(), self.hash_state);
+        for entry in self.entries.into_vec() {
+            map.insert_unique_erased(entry);
+        }
+        map
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    FrozenMap<E, S, I, N>
+{
+    #[inline]
+    fn find<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<usize>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = hash_def_key::<_, A, E, S>(&self.hash_state, k);
+        let mut is_key = equivalent_key::<A, E, Q, I, N>(k);
+        self.candidates(hash)
+            .find(|&idx| is_key(&self.entries[idx]))
+    }
+    #[inline]
+    /// Check if the map contains a value for the specified key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.find::<A, Q>(k).is_some()
+    }
+    #[inline]
+    /// Check if the map contains a value for the default key.
+    pub fn contains_default<A: 'static + ?Sized>(&self) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.contains_key::<A, _>(&Default::default())
+    }
+    #[inline]
+    /// Returns a reference to the entry corresponding to the key.
+    pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        match self.find::<A, Q>(k) {
+            Some(idx) => self.entries[idx].hash_entry::<A>(),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Returns a reference to the entry corresponding to the default key.
+    pub fn get_default<A: 'static + ?Sized>(&self) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get(&Default::default())
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        match self.find::<A, Q>(k) {
+            Some(idx) => self.entries[idx].get_mut::<A>(),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the default key.
+    pub fn get_default_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get_mut::<A, _>(&Default::default())
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    From<Map<E, S, I, N>> for FrozenMap<E, S, I, N>
+{
+    #[inline]
+    fn from(map: Map<E, S, I, N>) -> Self {
+        map.freeze()
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    From<FrozenMap<E, S, I, N>> for Map<E, S, I, N>
+{
+    #[inline]
+    fn from(map: FrozenMap<E, S, I, N>) -> Self {
+        map.thaw()
+    }
+}
+
+impl<
+        E: ?Sized,
+        S: BuildHasher + Clone,
+        I: ?Sized + HashableAny<S::Hasher> + DynClone,
+        const N: usize,
+    > Clone for FrozenMap<E, S, I, N>
+{
+    fn clone(&self) -> Self {
+        Self {
+            hash_state: self.hash_state.clone(),
+            layout: Layout {
+                seed: self.layout.seed,
+                disps: self.layout.disps.clone(),
+                slots: self.layout.slots.clone(),
+            },
+            starts: self.starts.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<
+        E: 'static + ?Sized,
+        S: BuildHasher,
+        S2: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher> + HashableAny<S2::Hasher> + DynPartialEq,
+        const N: usize,
+    > PartialEq<FrozenMap<E, S2, I, N>> for FrozenMap<E, S, I, N>
+{
+    fn eq(&self, rhs: &FrozenMap<E, S2, I, N>) -> bool {
+        if self.len() != rhs.len() {
+            return false;
+        }
+        // Entries are rehashed with the hash state of rhs, so the two maps need not share one.
+        self.iter().all(|entry| {
+            let rhash = hash_def_entry(&rhs.hash_state, entry.inner());
+            let is_entry = equivalent_entry(entry);
+            rhs.candidates(rhash).any(|idx| is_entry(&rhs.entries[idx]))
+        })
+    }
+}
+
+impl<
+        E: 'static + ?Sized,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher> + DynEq,
+        const N: usize,
+    > Eq for FrozenMap<E, S, I, N>
+{
+}
+
+impl<
+        E: ?Sized,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher> + DebugEntry,
+        const N: usize,
+    > Debug for FrozenMap<E, S, I, N>
+{
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt.debug_map()
+            .entries(self.iter().map(|e| (SomeKey(e), SomeValue(e))))
+            .finish()
+    }
+}
@@ -0,0 +1,472 @@
+//! An insertion-ordered [`IndexedMap`], with deterministic iteration and positional access.
+//!
+//! Entries are stored densely in a [`Vec`] in the order they were inserted, while a hash table
+//! of indices into that [`Vec`] is used for lookups by key, similar to the `indexmap` crate.
+use hashbrown::raw::RawTable;
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt::{Debug, Formatter};
+use core::hash::{BuildHasher, Hash};
+use alloc::vec::Vec;
+use crate::{
+    equivalent_entry, equivalent_key, hash_def_entry, hash_def_key, unreachable_internal_invariant,
+    CreateEntry, DebugEntry, DefaultHashBuilder, DEFAULT_INLINE_WORDS, DynClone, DynEq, DynPartialEq, DynStorage,
+    EntryAt, EntryFamily, ErasedEntry, ErasedEntryMut, HashEntry, HashableAny, KeyAt, Map, SomeKey, SomeValue,
+    ValueAt,
+};
+
+/// A hash map that remembers the order in which entries were inserted.
+///
+/// Iteration, [`Debug`] output and positional access via [`IndexedMap::get_index`] all follow
+/// that order. Replacing an entry keeps its position, removing an entry either moves the last
+/// entry into its place ([`IndexedMap::swap_remove`]) or shifts all following entries
+/// ([`IndexedMap::shift_remove`]).
+pub struct IndexedMap<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
+    const N: usize = DEFAULT_INLINE_WORDS,
+> {
+    hash_state: S,
+    // Indices into `entries`, hashed with the hash cached in the entry they point to
+    indices: RawTable<usize>,
+    entries: Vec<ErasedEntry<E, I, N>>,
+}
+
+type IterMut<'a, E, I, const N: usize> = core::iter::Map<
+    core::slice::IterMut<'a, ErasedEntry<E, I, N>>,
+    fn(&'a mut ErasedEntry<E, I, N>) -> ErasedEntryMut<'a, E, I, N>,
+>;
+
+#[inline]
+fn index_hasher<E: ?Sized, I: ?Sized, const N: usize>(
+    entries: &[ErasedEntry<E, I, N>],
+) -> impl '_ + Fn(&usize) -> u64 {
+    move |&idx| entries[idx].hash
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    IndexedMap<E, S, I, N>
+{
+    #[inline]
+    /// Create a new, empty, [`IndexedMap`].
+    pub fn new() -> Self
+    where
+        S: Default,
+    {
+        Self::with_hasher(S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`IndexedMap`] with space for at least `capacity` entries.
+    pub fn with_capacity(capacity: usize) -> Self
+    where
+        S: Default,
+    {
+        Self::with_capacity_and_hasher(capacity, S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`IndexedMap`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self::with_capacity_and_hasher(0, hash_state)
+    }
+    #[inline]
+    /// Create a new, empty, [`IndexedMap`] with space for at least `capacity` entries, which
+    /// will use the given hash builder to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_state: S) -> Self {
+        Self {
+            hash_state,
+            indices: RawTable::with_capacity(capacity),
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+    #[inline]
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hash_state
+    }
+    #[inline]
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    #[inline]
+    /// Check if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    #[inline]
+    /// Clear the map, but preserve the currently reserved capacity.
+    pub fn clear(&mut self) {
+        self.indices.clear();
+        self.entries.clear();
+    }
+    #[inline]
+    /// Iterate over all entries of the map, in order.
+    pub fn iter(&self) -> core::slice::Iter<'_, ErasedEntry<E, I, N>> {
+        self.entries.iter()
+    }
+    #[inline]
+    /// Iterate mutably over all entries of the map, in order.
+    pub fn iter_mut(&mut self) -> IterMut<'_, E, I, N> {
+        self.entries.iter_mut().map(ErasedEntryMut::new)
+    }
+    #[inline]
+    /// Get the entry at the given position.
+    pub fn get_index(&self, index: usize) -> Option<&ErasedEntry<E, I, N>> {
+        self.entries.get(index)
+    }
+    #[inline]
+    /// Get the entry at the given position mutably.
+    #[allow(clippy::manual_map)]
+    pub fn get_index_mut(&mut self, index: usize) -> Option<ErasedEntryMut<'_, E, I, N>> {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.entries.get_mut(index) {
+            Some(entry) => Some(ErasedEntryMut::new(entry)),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Get the first entry of the map.
+    pub fn first(&self) -> Option<&ErasedEntry<E, I, N>> {
+        self.entries.first()
+    }
+    #[inline]
+    /// Get the last entry of the map.
+    pub fn last(&self) -> Option<&ErasedEntry<E, I, N>> {
+        self.entries.last()
+    }
+    /// Remove the entry at the given position, by swapping it with the last entry.
+    ///
+    /// This perturbs the order of the map, but is O(1).
+    pub fn swap_remove_index(&mut self, index: usize) -> Option<ErasedEntry<E, I, N>> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.erase_index(index);
+        let last = self.entries.len() - 1;
+        if index != last {
+            // Point the index of the last entry at its new position
+            let hash = self.entries[last].hash;
+            match self.indices.get_mut(hash, |&idx| idx == last) {
+                Some(idx) => *idx = index,
+                None => unreachable_internal_invariant("every entry has an index"),
+            }
+        }
+        Some(self.entries.swap_remove(index))
+    }
+    /// Remove the entry at the given position, by shifting all following entries.
+    ///
+    /// This preserves the order of the map, but is O(n).
+    pub fn shift_remove_index(&mut self, index: usize) -> Option<ErasedEntry<E, I, N>> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.erase_index(index);
+        // holding a mutable reference to the table, the buckets stay valid
+        unsafe {
+            for bucket in self.indices.iter() {
+                let idx = bucket.as_mut();
+                if *idx > index {
+                    *idx -= 1;
+                }
+            }
+        }
+        Some(self.entries.remove(index))
+    }
+    /// Sort the entries of the map by the name of their argument type, see
+    /// [`ErasedEntry::type_name`].
+    ///
+    /// The sort is stable, entries of the same argument type keep their relative order.
+    pub fn sort_by_type_name(&mut self) {
+        self.sort_by(|lhs, rhs| lhs.type_name().cmp(rhs.type_name()))
+    }
+    /// Sort the entries of the map with a comparator function.
+    ///
+    /// The sort is stable.
+    pub fn sort_by(
+        &mut self,
+        compare: impl FnMut(&ErasedEntry<E, I, N>, &ErasedEntry<E, I, N>) -> Ordering,
+    ) {
+        self.entries.sort_by(compare);
+        self.rebuild_indices();
+    }
+    #[inline]
+    fn erase_index(&mut self, index: usize) {
+        let hash = self.entries[index].hash;
+        match self.indices.find(hash, |&idx| idx == index) {
+            // holding a mutable reference to the table, the bucket is valid
+            Some(bucket) => unsafe { self.indices.erase(bucket) },
+            None => unreachable_internal_invariant("every entry has an index"),
+        }
+    }
+    fn rebuild_indices(&mut self) {
+        self.indices.clear();
+        let hasher = index_hasher(&self.entries);
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let _ = self.indices.insert(entry.hash, idx, &hasher);
+        }
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    IndexedMap<E, S, I, N>
+{
+    #[inline]
+    fn hash_key<A: 'static + ?Sized, Q: ?Sized + Hash>(&self, key: &Q) -> u64
+    where
+        E: EntryFamily<A>,
+    {
+        hash_def_key::<_, A, E, S>(&self.hash_state, key)
+    }
+    /// Get the position of the entry for the given key.
+    pub fn get_index_of<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<usize>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, Q>(k);
+        let mut is_key = equivalent_key::<A, E, Q, I, N>(k);
+        let entries = &self.entries;
+        self.indices
+            .get(hash, |&idx| is_key(&entries[idx]))
+            .copied()
+    }
+    #[inline]
+    /// Check if the map contains an entry for the given key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.get_index_of::<A, Q>(k).is_some()
+    }
+    /// Get the entry stored for the given key.
+    pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        match self.get_index_of::<A, Q>(k) {
+            Some(idx) => self.entries[idx].hash_entry::<A>(),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Get the entry stored for the default key.
+    pub fn get_default<A: 'static + ?Sized>(&self) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get::<A, _>(&KeyAt::<E, A>::default())
+    }
+    /// Get mutable access to the value stored for the given key.
+    pub fn get_mut<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        match self.get_index_of::<A, Q>(k) {
+            Some(idx) => self.entries[idx].get_mut::<A>(),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Inserts an entry into the map.
+    ///
+    /// If the map did not have this key present, the entry is appended and [`None`] is returned.
+    ///
+    /// Otherwise, the entry is replaced in place and `Some(old)` where `old` is the old entry is
+    /// returned.
+    pub fn insert<A: 'static + ?Sized, P>(&mut self, entry: P) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+        P: Into<EntryAt<E, A>>,
+    {
+        self.insert_full(entry).1
+    }
+    /// Inserts an entry into the map, also returning the position of the entry.
+    ///
+    /// See [`IndexedMap::insert`].
+    pub fn insert_full<A: 'static + ?Sized, P>(
+        &mut self,
+        entry: P,
+    ) -> (usize, Option<EntryAt<E, A>>)
+    where
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let entry = entry.into();
+        let key = entry.split_ref().0;
+        let hash = self.hash_key::<A, _>(key);
+        let found = {
+            let mut is_key = equivalent_key::<A, E, _, I, N>(key);
+            let entries = &self.entries;
+            self.indices
+                .get(hash, |&idx| is_key(&entries[idx]))
+                .copied()
+        };
+        if let Some(idx) = found {
+            let old = match self.entries[idx].downcast_mut::<A>() {
+                Some(existing) => core::mem::replace(&mut existing.entry, entry),
+                None => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+            };
+            return (idx, Some(old));
+        }
+        let idx = self.entries.len();
+        self.entries.push(ErasedEntry::new(hash, entry));
+        let _ = self.indices.insert(hash, idx, index_hasher(&self.entries));
+        (idx, None)
+    }
+    /// Removes a key from the map by swapping it with the last entry, see
+    /// [`IndexedMap::swap_remove_index`].
+    pub fn swap_remove<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let idx = self.get_index_of::<A, Q>(k)?;
+        match self.swap_remove_index(idx)?.into_hash_entry::<A>() {
+            Ok(entry) => Some(entry),
+            Err(_) => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        }
+    }
+    /// Removes a key from the map by shifting all following entries, see
+    /// [`IndexedMap::shift_remove_index`].
+    pub fn shift_remove<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let idx = self.get_index_of::<A, Q>(k)?;
+        match self.shift_remove_index(idx)?.into_hash_entry::<A>() {
+            Ok(entry) => Some(entry),
+            Err(_) => unreachable_internal_invariant("hash+equivalent key for the correct type"),
+        }
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> IntoIterator
+    for IndexedMap<E, S, I, N>
+{
+    type Item = ErasedEntry<E, I, N>;
+    type IntoIter = alloc::vec::IntoIter<ErasedEntry<E, I, N>>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> IntoIterator
+    for &'a IndexedMap<E, S, I, N>
+{
+    type Item = &'a ErasedEntry<E, I, N>;
+    type IntoIter = core::slice::Iter<'a, ErasedEntry<E, I, N>>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize> IntoIterator
+    for &'a mut IndexedMap<E, S, I, N>
+{
+    type Item = ErasedEntryMut<'a, E, I, N>;
+    type IntoIter = IterMut<'a, E, I, N>;
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    From<Map<E, S, I, N>> for IndexedMap<E, S, I, N>
+{
+    /// Convert a [`Map`] into an [`IndexedMap`], ordering the entries as they are iterated in
+    /// the map.
+    fn from(map: Map<E, S, I, N>) -> Self {
+        let (hash_state, entries) = map.into_raw_parts();
+        let mut map = Self::with_hasher(hash_state);
+        map.entries = entries.collect();
+        map.rebuild_indices();
+        map
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher + Default, I: ?Sized + HashableAny<S::Hasher>, const N: usize>
+    Default for IndexedMap<E, S, I, N>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<
+        E: ?Sized,
+        S: BuildHasher + Clone,
+        I: ?Sized + HashableAny<S::Hasher> + DynClone,
+        const N: usize,
+    > Clone for IndexedMap<E, S, I, N>
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            hash_state: self.hash_state.clone(),
+            indices: self.indices.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<
+        E: 'static + ?Sized,
+        S: BuildHasher,
+        S2: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher> + HashableAny<S2::Hasher> + DynPartialEq,
+        const N: usize,
+    > PartialEq<IndexedMap<E, S2, I, N>> for IndexedMap<E, S, I, N>
+{
+    /// Maps compare equal if they contain the same entries, regardless of their order.
+    fn eq(&self, rhs: &IndexedMap<E, S2, I, N>) -> bool {
+        if self.len() != rhs.len() {
+            return false;
+        }
+
+        // Entries are rehashed with the hash state of rhs, so the two maps need not share one.
+        self.iter().all(|entry| {
+            let rhash = hash_def_entry(&rhs.hash_state, entry.inner());
+            let is_equal = equivalent_entry(entry);
+            rhs.indices
+                .get(rhash, |&idx| is_equal(&rhs.entries[idx]))
+                .is_some()
+        })
+    }
+}
+
+impl<
+        E: 'static + ?Sized,
+        S: BuildHasher,
+        I: ?Sized + HashableAny<S::Hasher> + DynEq,
+        const N: usize,
+    > Eq for IndexedMap<E, S, I, N>
+{
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher> + DebugEntry, const N: usize>
+    Debug for IndexedMap<E, S, I, N>
+{
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt.debug_map()
+            .entries(self.iter().map(|e| (SomeKey(e), SomeValue(e))))
+            .finish()
+    }
+}
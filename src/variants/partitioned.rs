@@ -0,0 +1,450 @@
+//! A [`PartitionedMap`], keeping the entries of each argument type in a separate typed table.
+//!
+//! [`Map`] stores all entries in a single table, hashing the [`TypeId`] of the argument type
+//! together with the key, and downcasting every probed entry to compare keys. This map instead
+//! looks up the table of the argument type by its [`TypeId`] first, then probes that table with
+//! the key alone and compares keys directly.
+//!
+//! [`Map`]: crate::Map
+use hashbrown::raw::RawTable;
+use hashbrown::HashMap;
+use core::any::TypeId;
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash, Hasher};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use crate::{
+    unreachable_internal_invariant, DefaultHashBuilder, EntryAt, EntryFamily, HashEntry, KeyAt,
+    NonOwningPhantomPointer, RefAny, ValueAt,
+};
+
+// All entries stored for the argument type `A`
+struct SubTable<E: ?Sized + EntryFamily<A>, A: ?Sized> {
+    raw: RawTable<EntryAt<E, A>>,
+    _marker: NonOwningPhantomPointer<A>,
+}
+
+// The operations on a `SubTable` that don't need to know the argument type
+trait ErasedTable: RefAny {
+    fn len(&self) -> usize;
+    fn clear(&mut self);
+    fn type_name(&self) -> &'static str;
+}
+
+impl<E: 'static + ?Sized + EntryFamily<A>, A: 'static + ?Sized> ErasedTable for SubTable<E, A> {
+    #[inline]
+    fn len(&self) -> usize {
+        self.raw.len()
+    }
+    #[inline]
+    fn clear(&mut self) {
+        self.raw.clear()
+    }
+    #[inline]
+    fn type_name(&self) -> &'static str {
+        core::any::type_name::<A>()
+    }
+}
+
+#[inline]
+fn equivalent<'q, A: ?Sized, E: ?Sized + EntryFamily<A>, Q: ?Sized + Eq>(
+    k: &'q Q,
+) -> impl 'q + Fn(&EntryAt<E, A>) -> bool
+where
+    KeyAt<E, A>: Borrow<Q>,
+{
+    move |entry| k == entry.split_ref().0.borrow()
+}
+
+#[inline]
+fn make_hash<Q: ?Sized + Hash, S: BuildHasher>(hash_state: &S, key: &Q) -> u64 {
+    let mut hasher = hash_state.build_hasher();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[inline]
+fn make_hasher<A: ?Sized, E: ?Sized + EntryFamily<A>, S: BuildHasher>(
+    hash_state: &S,
+) -> impl '_ + Fn(&EntryAt<E, A>) -> u64 {
+    move |entry| make_hash(hash_state, entry.split_ref().0)
+}
+
+#[inline]
+fn downcast_table_mut<A: 'static + ?Sized, E: 'static + ?Sized + EntryFamily<A>>(
+    tables: &mut HashMap<TypeId, Box<dyn ErasedTable>>,
+) -> Option<&mut SubTable<E, A>> {
+    match tables.get_mut(&TypeId::of::<A>()) {
+        Some(table) => match (**table).any_mut().downcast_mut() {
+            Some(table) => Some(table),
+            None => unreachable_internal_invariant("tables are stored for their argument type"),
+        },
+        None => None,
+    }
+}
+
+/// A hash map storing the entries of each argument type in a separate, typed, table.
+///
+/// Compared to [`Map`], lookups have to go through two tables, but keys are hashed and
+/// compared without dynamic dispatch. Operations concerning all entries of a single argument
+/// type, such as [`PartitionedMap::count_of`], [`PartitionedMap::iter_of`] and
+/// [`PartitionedMap::remove_type`], only visit the entries of that type.
+///
+/// The first entry of each argument type allocates a new table, so [`Map`] is the better fit
+/// for many argument types with few entries each.
+///
+/// [`Map`]: crate::Map
+pub struct PartitionedMap<E: ?Sized, S: BuildHasher = DefaultHashBuilder> {
+    hash_state: S,
+    // Maps the type id of `A` to the `SubTable<E, A>` of entries stored for `A`
+    tables: HashMap<TypeId, Box<dyn ErasedTable>>,
+    len: usize,
+    _marker: NonOwningPhantomPointer<E>,
+}
+
+impl<E: ?Sized, S: BuildHasher> PartitionedMap<E, S> {
+    #[inline]
+    /// Create a new, empty, [`PartitionedMap`].
+    pub fn new() -> Self
+    where
+        S: Default,
+    {
+        Self::with_hasher(S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`PartitionedMap`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self {
+            hash_state,
+            tables: HashMap::new(),
+            len: 0,
+            _marker: core::marker::PhantomData,
+        }
+    }
+    #[inline]
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hash_state
+    }
+    #[inline]
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    /// Check if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    #[inline]
+    /// Clear the map, but preserve the currently reserved capacity of each type.
+    pub fn clear(&mut self) {
+        for table in self.tables.values_mut() {
+            table.clear();
+        }
+        self.len = 0;
+    }
+    /// The names of the argument types with at least one entry in the map, in arbitrary order.
+    pub fn type_names(&self) -> impl '_ + Iterator<Item = &'static str> {
+        self.tables
+            .values()
+            .filter(|table| table.len() != 0)
+            .map(|table| table.type_name())
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher> PartitionedMap<E, S> {
+    #[inline]
+    fn table<A: 'static + ?Sized>(&self) -> Option<&SubTable<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        match self.tables.get(&TypeId::of::<A>()) {
+            // Careful to not call `any_ref` on the Box
+            Some(table) => match (**table).any_ref().downcast_ref() {
+                Some(table) => Some(table),
+                None => unreachable_internal_invariant("tables are stored for their argument type"),
+            },
+            None => None,
+        }
+    }
+    #[inline]
+    fn table_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut SubTable<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        downcast_table_mut::<A, E>(&mut self.tables)
+    }
+    #[inline]
+    fn take_table<A: 'static + ?Sized>(&mut self) -> Option<SubTable<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        match self.tables.remove(&TypeId::of::<A>()) {
+            Some(table) => match table.any_box().downcast::<SubTable<E, A>>() {
+                Ok(table) => {
+                    self.len -= table.raw.len();
+                    Some(*table)
+                }
+                Err(_) => unreachable_internal_invariant("tables are stored for their argument type"),
+            },
+            None => None,
+        }
+    }
+    #[inline]
+    /// Check if the map contains a value for the specified key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.get::<A, Q>(k).is_some()
+    }
+    #[inline]
+    /// Check if the map contains a value for the default key.
+    pub fn contains_default<A: 'static + ?Sized>(&self) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.contains_key::<A, _>(&Default::default())
+    }
+    #[inline]
+    /// Returns a reference to the entry corresponding to the key.
+    pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let table = self.table::<A>()?;
+        let hash = make_hash(&self.hash_state, k);
+        table.raw.get(hash, equivalent::<A, E, Q>(k))
+    }
+    #[inline]
+    /// Returns a reference to the entry corresponding to the default key.
+    pub fn get_default<A: 'static + ?Sized>(&self) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get(&Default::default())
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = make_hash(&self.hash_state, k);
+        let table = self.table_mut::<A>()?;
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match table.raw.get_mut(hash, equivalent::<A, E, Q>(k)) {
+            Some(entry) => Some(entry.split_mut().1),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Returns a mutable reference to the value corresponding to the default key.
+    pub fn get_default_mut<A: 'static + ?Sized>(&mut self) -> Option<&mut ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get_mut::<A, _>(&Default::default())
+    }
+    /// Inserts an entry into the map.
+    ///
+    /// If the map did not have this key present, [`None`] is returned.
+    ///
+    /// Otherwise, the entry is fully replaced and `Some(old)` where `old` is the old entry is returned.
+    pub fn insert<A: 'static + ?Sized, P>(&mut self, entry: P) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let entry = entry.into();
+        let hash_state = &self.hash_state;
+        let hash = make_hash(hash_state, entry.split_ref().0);
+        let table = self
+            .tables
+            .entry(TypeId::of::<A>())
+            .or_insert_with(|| {
+                Box::new(SubTable::<E, A> {
+                    raw: RawTable::new(),
+                    _marker: core::marker::PhantomData,
+                })
+            });
+        let table: &mut SubTable<E, A> = match (**table).any_mut().downcast_mut() {
+            Some(table) => table,
+            None => unreachable_internal_invariant("tables are stored for their argument type"),
+        };
+        let key = entry.split_ref().0;
+        if let Some(existing) = table.raw.get_mut(hash, equivalent::<A, E, _>(key)) {
+            Some(core::mem::replace(existing, entry))
+        } else {
+            let _ = table.raw.insert(hash, entry, make_hasher::<A, E, S>(hash_state));
+            self.len += 1;
+            None
+        }
+    }
+    /// Removes a key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    pub fn remove_entry<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = make_hash(&self.hash_state, k);
+        let table = self.table_mut::<A>()?;
+        let entry = table.raw.remove_entry(hash, equivalent::<A, E, Q>(k))?;
+        self.len -= 1;
+        Some(entry)
+    }
+    #[inline]
+    /// Removes the default key from the map, returning the entry at the key if the key
+    /// was previously in the map.
+    pub fn remove_default<A: 'static + ?Sized>(&mut self) -> Option<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.remove_entry::<A, _>(&Default::default())
+    }
+    /// Removes a key from the map, returning the value at the key if the key
+    /// was previously in the map.
+    #[allow(clippy::manual_map)]
+    pub fn remove<A: 'static + ?Sized, Q: ?Sized>(&mut self, k: &Q) -> Option<ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match self.remove_entry::<A, Q>(k) {
+            Some(v) => Some(v.into_parts().1),
+            None => None,
+        }
+    }
+    #[inline]
+    /// An iterator visiting all entries stored for the argument type `A` in arbitrary order.
+    pub fn iter_of<A: 'static + ?Sized>(&self) -> impl '_ + Iterator<Item = &'_ EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        // The iterators borrow the table, preventing use-after-free
+        self.table::<A>()
+            .into_iter()
+            .flat_map(|table| unsafe { table.raw.iter() }.map(|bucket| unsafe { bucket.as_ref() }))
+    }
+    #[inline]
+    /// An iterator visiting all entries stored for the argument type `A` in arbitrary order,
+    /// with mutable access to the values.
+    pub fn iter_of_mut<A: 'static + ?Sized>(
+        &mut self,
+    ) -> impl '_ + Iterator<Item = (&'_ KeyAt<E, A>, &'_ mut ValueAt<E, A>)>
+    where
+        E: EntryFamily<A>,
+    {
+        // The iterators borrow the table mutably, preventing use-after-free and aliasing
+        self.table_mut::<A>().into_iter().flat_map(|table| {
+            unsafe { table.raw.iter() }.map(|bucket| unsafe { bucket.as_mut() }.split_mut())
+        })
+    }
+    #[inline]
+    /// An iterator visiting all keys stored for the argument type `A` in arbitrary order.
+    pub fn keys_of<A: 'static + ?Sized>(&self) -> impl '_ + Iterator<Item = &'_ KeyAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter_of::<A>().map(|e| e.split_ref().0)
+    }
+    #[inline]
+    /// An iterator visiting all values stored for the argument type `A` in arbitrary order.
+    pub fn values_of<A: 'static + ?Sized>(&self) -> impl '_ + Iterator<Item = &'_ ValueAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        self.iter_of::<A>().map(|e| e.split_ref().1)
+    }
+    #[inline]
+    /// Count the entries stored for the argument type `A`.
+    pub fn count_of<A: 'static + ?Sized>(&self) -> usize
+    where
+        E: EntryFamily<A>,
+    {
+        match self.table::<A>() {
+            Some(table) => table.raw.len(),
+            None => 0,
+        }
+    }
+    #[inline]
+    /// Check if the map contains any entry of argument type `A`, regardless of its key.
+    pub fn contains_type<A: 'static + ?Sized>(&self) -> bool
+    where
+        E: EntryFamily<A>,
+    {
+        self.count_of::<A>() != 0
+    }
+    #[inline]
+    /// Removes all entries of argument type `A`, regardless of their key.
+    ///
+    /// Returns the number of removed entries.
+    pub fn remove_type<A: 'static + ?Sized>(&mut self) -> usize
+    where
+        E: EntryFamily<A>,
+    {
+        match self.take_table::<A>() {
+            Some(table) => table.raw.len(),
+            None => 0,
+        }
+    }
+    #[inline]
+    /// Removes all entries of argument type `A`, regardless of their key, and returns them.
+    pub fn take_type<A: 'static + ?Sized>(&mut self) -> Vec<EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+    {
+        match self.take_table::<A>() {
+            Some(table) => table.raw.into_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+    /// Retains only the entries of argument type `A` specified by the predicate.
+    /// Entries of other types are always retained.
+    ///
+    /// In other words, remove all entries `(k, v)` of type `A` for which `f(&k, &mut v)` returns `false`.
+    pub fn retain_of<A: 'static + ?Sized>(
+        &mut self,
+        mut f: impl FnMut(&KeyAt<E, A>, &mut ValueAt<E, A>) -> bool,
+    ) where
+        E: EntryFamily<A>,
+    {
+        // Borrow the tables on their own, to keep `len` up to date if `f` panics
+        let table = match downcast_table_mut::<A, E>(&mut self.tables) {
+            Some(table) => table,
+            None => return,
+        };
+        // Here we only use `iter` as a temporary, preventing use-after-free
+        unsafe {
+            for bucket in table.raw.iter() {
+                let (key, value) = bucket.as_mut().split_mut();
+                if !f(key, value) {
+                    table.raw.erase(bucket);
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher + Default> Default for PartitionedMap<E, S> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
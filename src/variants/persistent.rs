@@ -0,0 +1,567 @@
+//! An immutable [`PersistentMap`] that shares structure between versions.
+//!
+//! The map is a hash array mapped trie (HAMT) over the same hash that [`Map`] uses for its
+//! entries. Each node branches on 5 bits of the hash, and entries are held in [`Arc`]s, so
+//! cloning a map is O(1) and a new version created by [`PersistentMap::insert`] or
+//! [`PersistentMap::remove`] only copies the nodes on the path to the changed entry.
+use core::borrow::Borrow;
+use core::fmt::{Debug, Formatter};
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::{
+    hash_def_key, CreateEntry, DebugEntry, DefaultHashBuilder, DynStorage, EntryAt, EntryFamily,
+    HashEntry, HashableAny, InnerEntry, KeyAt, Map, NonOwningPhantomPointer, RefAny,
+};
+
+const BITS_PER_LEVEL: u32 = 5;
+const LEVEL_MASK: u64 = (1 << BITS_PER_LEVEL) - 1;
+
+/// A persistent hash map. Modifications return a new version of the map, leaving the old
+/// version intact.
+///
+/// Cloning the map is O(1), lookups, insertions and removals are O(log n). Since entries are
+/// shared between versions, no [`Clone`] bound is needed on the entries, and a map with storage
+/// that is [`Send`] and [`Sync`] can be handed out to readers on other threads as a snapshot.
+pub struct PersistentMap<
+    E: ?Sized,
+    S: BuildHasher = DefaultHashBuilder,
+    I: ?Sized + HashableAny<S::Hasher> = DynStorage<S>,
+> {
+    root: Arc<Node<I>>,
+    len: usize,
+    hash_state: S,
+    _marker: NonOwningPhantomPointer<E>,
+}
+
+struct Leaf<I: ?Sized> {
+    hash: u64,
+    inner: Arc<I>,
+}
+
+enum Child<I: ?Sized> {
+    Leaf(Leaf<I>),
+    Node(Arc<Node<I>>),
+}
+
+enum Node<I: ?Sized> {
+    // Holds one child for each bit set in the bitmap, in order of the bits
+    Branch {
+        bitmap: u32,
+        children: Vec<Child<I>>,
+    },
+    // All hash bits are used up, the leaves all have the same hash
+    Collision(Vec<Leaf<I>>),
+}
+
+// Result of removing a leaf from a node, collapsing nodes that are no longer needed
+enum Removed<I: ?Sized> {
+    Empty,
+    Leaf(Leaf<I>),
+    Node(Node<I>),
+}
+
+impl<I: ?Sized> Clone for Leaf<I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            hash: self.hash,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<I: ?Sized> Clone for Child<I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        match self {
+            Child::Leaf(leaf) => Child::Leaf(leaf.clone()),
+            Child::Node(node) => Child::Node(node.clone()),
+        }
+    }
+}
+
+#[inline]
+fn fragment(hash: u64, shift: u32) -> u32 {
+    ((hash >> shift) & LEVEL_MASK) as u32
+}
+
+impl<I: ?Sized> Node<I> {
+    #[inline]
+    fn empty() -> Self {
+        Node::Branch {
+            bitmap: 0,
+            children: Vec::new(),
+        }
+    }
+
+    fn get(&self, hash: u64, is_match: impl Fn(&I) -> bool) -> Option<&I> {
+        let mut node = self;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let bit = 1 << fragment(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &children[(bitmap & (bit - 1)).count_ones() as usize] {
+                        Child::Leaf(leaf) if leaf.hash == hash && is_match(&leaf.inner) => {
+                            return Some(&leaf.inner)
+                        }
+                        Child::Leaf(_) => return None,
+                        Child::Node(child) => node = child,
+                    }
+                    shift += BITS_PER_LEVEL;
+                }
+                Node::Collision(leaves) => {
+                    return leaves
+                        .iter()
+                        .find(|leaf| leaf.hash == hash && is_match(&leaf.inner))
+                        .map(|leaf| &*leaf.inner)
+                }
+            }
+        }
+    }
+
+    // Builds the smallest subtree at `shift` containing both leaves
+    fn pair(first: Leaf<I>, second: Leaf<I>, shift: u32) -> Self {
+        if shift >= u64::BITS {
+            return Node::Collision(vec![first, second]);
+        }
+        let (first_frag, second_frag) = (fragment(first.hash, shift), fragment(second.hash, shift));
+        if first_frag == second_frag {
+            let child = Node::pair(first, second, shift + BITS_PER_LEVEL);
+            Node::Branch {
+                bitmap: 1 << first_frag,
+                children: vec![Child::Node(Arc::new(child))],
+            }
+        } else {
+            let children = if first_frag < second_frag {
+                vec![Child::Leaf(first), Child::Leaf(second)]
+            } else {
+                vec![Child::Leaf(second), Child::Leaf(first)]
+            };
+            Node::Branch {
+                bitmap: (1 << first_frag) | (1 << second_frag),
+                children,
+            }
+        }
+    }
+
+    // Returns the new node, and whether an existing leaf was replaced
+    fn insert(&self, leaf: Leaf<I>, shift: u32, is_match: &impl Fn(&I) -> bool) -> (Self, bool) {
+        match self {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << fragment(leaf.hash, shift);
+                let idx = (bitmap & (bit - 1)).count_ones() as usize;
+                let mut children = children.clone();
+                if bitmap & bit == 0 {
+                    children.insert(idx, Child::Leaf(leaf));
+                    let node = Node::Branch {
+                        bitmap: bitmap | bit,
+                        children,
+                    };
+                    return (node, false);
+                }
+                let replaced = match &children[idx] {
+                    Child::Leaf(existing)
+                        if existing.hash == leaf.hash && is_match(&existing.inner) =>
+                    {
+                        children[idx] = Child::Leaf(leaf);
+                        true
+                    }
+                    Child::Leaf(existing) => {
+                        let node = Node::pair(existing.clone(), leaf, shift + BITS_PER_LEVEL);
+                        children[idx] = Child::Node(Arc::new(node));
+                        false
+                    }
+                    Child::Node(child) => {
+                        let (node, replaced) = child.insert(leaf, shift + BITS_PER_LEVEL, is_match);
+                        children[idx] = Child::Node(Arc::new(node));
+                        replaced
+                    }
+                };
+                let node = Node::Branch {
+                    bitmap: *bitmap,
+                    children,
+                };
+                (node, replaced)
+            }
+            Node::Collision(leaves) => {
+                let mut leaves = leaves.clone();
+                match leaves.iter().position(|existing| is_match(&existing.inner)) {
+                    Some(pos) => {
+                        leaves[pos] = leaf;
+                        (Node::Collision(leaves), true)
+                    }
+                    None => {
+                        leaves.push(leaf);
+                        (Node::Collision(leaves), false)
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns `None` if no matching leaf was found
+    fn remove(&self, hash: u64, shift: u32, is_match: &impl Fn(&I) -> bool) -> Option<Removed<I>> {
+        match self {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << fragment(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let idx = (bitmap & (bit - 1)).count_ones() as usize;
+                let replacement = match &children[idx] {
+                    Child::Leaf(leaf) if leaf.hash == hash && is_match(&leaf.inner) => None,
+                    Child::Leaf(_) => return None,
+                    Child::Node(child) => {
+                        match child.remove(hash, shift + BITS_PER_LEVEL, is_match)? {
+                            Removed::Empty => None,
+                            Removed::Leaf(leaf) => Some(Child::Leaf(leaf)),
+                            Removed::Node(node) => Some(Child::Node(Arc::new(node))),
+                        }
+                    }
+                };
+                let mut children = children.clone();
+                let bitmap = match replacement {
+                    Some(child) => {
+                        children[idx] = child;
+                        *bitmap
+                    }
+                    None => {
+                        let _ = children.remove(idx);
+                        bitmap & !bit
+                    }
+                };
+                // Pull up single leaves, so that the trie stays as shallow as possible
+                if children.is_empty() {
+                    Some(Removed::Empty)
+                } else if let [Child::Leaf(leaf)] = &children[..] {
+                    Some(Removed::Leaf(leaf.clone()))
+                } else {
+                    Some(Removed::Node(Node::Branch { bitmap, children }))
+                }
+            }
+            Node::Collision(leaves) => {
+                let pos = leaves.iter().position(|leaf| is_match(&leaf.inner))?;
+                let mut leaves = leaves.clone();
+                let _ = leaves.remove(pos);
+                if let [leaf] = &leaves[..] {
+                    Some(Removed::Leaf(leaf.clone()))
+                } else {
+                    Some(Removed::Node(Node::Collision(leaves)))
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the storage of all entries of a [`PersistentMap`], see
+/// [`PersistentMap::iter`].
+pub struct Iter<'a, I: ?Sized> {
+    stack: Vec<core::slice::Iter<'a, Child<I>>>,
+    collision: core::slice::Iter<'a, Leaf<I>>,
+    remaining: usize,
+}
+
+impl<'a, I: ?Sized> Iterator for Iter<'a, I> {
+    type Item = &'a I;
+    fn next(&mut self) -> Option<&'a I> {
+        loop {
+            if let Some(leaf) = self.collision.next() {
+                self.remaining -= 1;
+                return Some(&leaf.inner);
+            }
+            let top = self.stack.last_mut()?;
+            match top.next() {
+                Some(Child::Leaf(leaf)) => {
+                    self.remaining -= 1;
+                    return Some(&leaf.inner);
+                }
+                Some(Child::Node(node)) => match &**node {
+                    Node::Branch { children, .. } => self.stack.push(children.iter()),
+                    Node::Collision(leaves) => self.collision = leaves.iter(),
+                },
+                None => {
+                    let _ = self.stack.pop();
+                }
+            }
+        }
+    }
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, I: ?Sized> ExactSizeIterator for Iter<'a, I> {}
+impl<'a, I: ?Sized> core::iter::FusedIterator for Iter<'a, I> {}
+
+#[inline]
+fn is_key<A: 'static + ?Sized, E: 'static + ?Sized, Q: ?Sized + Eq, I: ?Sized + RefAny>(
+    key: &Q,
+) -> impl '_ + Fn(&I) -> bool
+where
+    E: EntryFamily<A>,
+    KeyAt<E, A>: Borrow<Q>,
+{
+    move |inner| match downcast_ref::<A, E, I>(inner) {
+        Some(r) => key == r.key().borrow(),
+        None => false,
+    }
+}
+
+#[inline]
+fn downcast_ref<A: 'static + ?Sized, E: 'static + ?Sized, I: ?Sized + RefAny>(
+    inner: &I,
+) -> Option<&InnerEntry<E, A>>
+where
+    E: EntryFamily<A>,
+{
+    inner.any_ref().downcast_ref::<InnerEntry<E, A>>()
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> PersistentMap<E, S, I> {
+    #[inline]
+    /// Create a new, empty, [`PersistentMap`].
+    pub fn new() -> Self
+    where
+        S: Default,
+    {
+        Self::with_hasher(S::default())
+    }
+    #[inline]
+    /// Create a new, empty, [`PersistentMap`] which will use the given hash builder to hash keys.
+    pub fn with_hasher(hash_state: S) -> Self {
+        Self {
+            root: Arc::new(Node::empty()),
+            len: 0,
+            hash_state,
+            _marker: PhantomData,
+        }
+    }
+    #[inline]
+    /// Returns a reference to the map's [`BuildHasher`].
+    pub fn hasher(&self) -> &S {
+        &self.hash_state
+    }
+    #[inline]
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    #[inline]
+    /// Check if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    #[inline]
+    /// Check if both maps are the same version, i.e. one is a clone of the other and neither
+    /// has been replaced by a modified version since. This is a cheap pointer comparison.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+    #[inline]
+    /// Iterate over the storage of all entries in the map, in unspecified order.
+    pub fn iter(&self) -> Iter<'_, I> {
+        let (stack, collision) = match &*self.root {
+            Node::Branch { children, .. } => (vec![children.iter()], [].iter()),
+            Node::Collision(leaves) => (Vec::new(), leaves.iter()),
+        };
+        Iter {
+            stack,
+            collision,
+            remaining: self.len,
+        }
+    }
+}
+
+impl<E: 'static + ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>>
+    PersistentMap<E, S, I>
+{
+    #[inline]
+    fn hash_key<A: 'static + ?Sized, Q: ?Sized + Hash>(&self, key: &Q) -> u64
+    where
+        E: EntryFamily<A>,
+    {
+        hash_def_key::<_, A, E, S>(&self.hash_state, key)
+    }
+    /// Check if the map contains an entry for the given key.
+    pub fn contains_key<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> bool
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.get::<A, Q>(k).is_some()
+    }
+    /// Get the entry stored for the given key.
+    pub fn get<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, Q>(k);
+        let inner = self.root.get(hash, is_key::<A, E, Q, I>(k))?;
+        // Avoid `Option::map` because it bloats LLVM IR.
+        match downcast_ref::<A, E, I>(inner) {
+            Some(inner) => Some(inner),
+            None => None,
+        }
+    }
+    #[inline]
+    /// Get the entry stored for the default key.
+    pub fn get_default<A: 'static + ?Sized>(&self) -> Option<&EntryAt<E, A>>
+    where
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Default,
+    {
+        self.get::<A, _>(&KeyAt::<E, A>::default())
+    }
+    /// Returns a new version of the map, with the entry inserted.
+    ///
+    /// If the map already contains the key, the entry is replaced in the new version.
+    /// Only the nodes on the path to the entry are copied, the rest is shared with `self`.
+    #[must_use = "the map is not modified, a new version is returned"]
+    pub fn insert<A: 'static + ?Sized, P>(&self, entry: P) -> Self
+    where
+        S: Clone,
+        E: EntryFamily<A>,
+        I: CreateEntry<A, E>,
+        P: Into<EntryAt<E, A>>,
+    {
+        let entry = entry.into();
+        let hash = self.hash_key::<A, _>(entry.split_ref().0);
+        let inner: Arc<I> = Arc::from(I::from_entry(entry));
+        let is_match = |other: &I| match downcast_ref::<A, E, I>(other) {
+            Some(other) => match downcast_ref::<A, E, I>(&inner) {
+                Some(inner) => other.key() == inner.key(),
+                None => false,
+            },
+            None => false,
+        };
+        let (root, replaced) = self.root.insert(
+            Leaf {
+                hash,
+                inner: inner.clone(),
+            },
+            0,
+            &is_match,
+        );
+        Self {
+            root: Arc::new(root),
+            len: if replaced { self.len } else { self.len + 1 },
+            hash_state: self.hash_state.clone(),
+            _marker: PhantomData,
+        }
+    }
+    /// Returns a new version of the map, with the given key removed.
+    ///
+    /// If the map does not contain the key, the new version is a clone of `self`.
+    #[must_use = "the map is not modified, a new version is returned"]
+    pub fn remove<A: 'static + ?Sized, Q: ?Sized>(&self, k: &Q) -> Self
+    where
+        S: Clone,
+        E: EntryFamily<A>,
+        KeyAt<E, A>: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let hash = self.hash_key::<A, Q>(k);
+        let root = match self.root.remove(hash, 0, &is_key::<A, E, Q, I>(k)) {
+            None => return self.clone(),
+            Some(Removed::Empty) => Node::empty(),
+            // The root always stays a branch, so that the leaf is found at the first level
+            Some(Removed::Leaf(leaf)) => Node::empty().insert(leaf, 0, &|_| false).0,
+            Some(Removed::Node(node)) => node,
+        };
+        Self {
+            root: Arc::new(root),
+            len: self.len - 1,
+            hash_state: self.hash_state.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher + Clone, I: ?Sized + HashableAny<S::Hasher>> Clone
+    for PersistentMap<E, S, I>
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+            hash_state: self.hash_state.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher + Default, I: ?Sized + HashableAny<S::Hasher>> Default
+    for PersistentMap<E, S, I>
+{
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> From<Map<E, S, I>>
+    for PersistentMap<E, S, I>
+{
+    /// Convert a [`Map`] into a [`PersistentMap`], reusing the hashes stored in the map.
+    fn from(map: Map<E, S, I>) -> Self {
+        let (hash_state, entries) = map.into_raw_parts();
+        let mut root = Node::empty();
+        let mut len = 0;
+        for entry in entries {
+            // The keys of a map are distinct, no need to check for an existing entry
+            root = root
+                .insert(
+                    Leaf {
+                        hash: entry.hash,
+                        inner: Arc::from(entry.inner.into_box()),
+                    },
+                    0,
+                    &|_| false,
+                )
+                .0;
+            len += 1;
+        }
+        Self {
+            root: Arc::new(root),
+            len,
+            hash_state,
+            _marker: PhantomData,
+        }
+    }
+}
+
+struct SomeKey<'a, I: ?Sized>(&'a I);
+struct SomeValue<'a, I: ?Sized>(&'a I);
+
+impl<'a, I: ?Sized + DebugEntry> Debug for SomeKey<'a, I> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt_key(f)
+    }
+}
+impl<'a, I: ?Sized + DebugEntry> Debug for SomeValue<'a, I> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt_value(f)
+    }
+}
+
+impl<E: ?Sized, S: BuildHasher, I: ?Sized + HashableAny<S::Hasher> + DebugEntry> Debug
+    for PersistentMap<E, S, I>
+{
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt.debug_map()
+            .entries(self.iter().map(|e| (SomeKey(e), SomeValue(e))))
+            .finish()
+    }
+}
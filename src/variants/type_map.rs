@@ -0,0 +1,360 @@
+//! A [`TypeMap`], storing at most one value for each type, with a fast path for lookups.
+//!
+//! With the [`Singleton`] family the key of every entry is `()`, so a [`Map<Singleton>`] ends up
+//! hashing only the [`TypeId`] of the argument type, with a general purpose hasher. This map
+//! instead uses the [`TypeId`] directly as the hash, which is a constant for every known type.
+//!
+//! [`Map<Singleton>`]: crate::Map
+use hashbrown::raw::{Bucket, RawTable};
+use core::any::TypeId;
+use core::fmt::{Debug, Formatter};
+use core::hash::{BuildHasher, Hash, Hasher};
+use core::marker::PhantomData;
+use crate::families::Singleton;
+use crate::{
+    CreateEntry, DebugEntry, DefaultHashBuilder, DynClone, DynStorage, ErasedEntry, HashableAny,
+    Map, SomeKey, SomeValue,
+};
+
+type Slot<I> = (TypeId, ErasedEntry<Singleton, I>);
+
+// Passes through the hash written by `TypeId`, which is already a good hash.
+#[derive(Default)]
+struct TypeIdHasher(u64);
+
+impl Hasher for TypeIdHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        // Not expected to be called, `TypeId` writes a single u64
+        for &byte in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(byte);
+        }
+    }
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[inline]
+fn hash_type_id(type_id: TypeId) -> u64 {
+    let mut hasher = TypeIdHasher::default();
+    type_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[inline]
+fn slot_hasher<I: ?Sized>(slot: &Slot<I>) -> u64 {
+    slot.1.hash
+}
+
+/// A map storing at most one value for each type, see [`Singleton`].
+///
+/// In contrast to [`Map<Singleton>`], values are looked up by their [`TypeId`] alone, without
+/// hashing a key or going through the vtable of the stored entries.
+///
+/// [`Map<Singleton>`]: crate::Map
+pub struct TypeMap<I: ?Sized = DynStorage<DefaultHashBuilder>> {
+    raw: RawTable<Slot<I>>,
+}
+
+impl<I: ?Sized> TypeMap<I> {
+    #[inline]
+    /// Create a new, empty, [`TypeMap`].
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+    #[inline]
+    /// Create a new, empty, [`TypeMap`] with space for at least `capacity` values.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            raw: RawTable::with_capacity(capacity),
+        }
+    }
+    #[inline]
+    /// The number of values the map can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.raw.capacity()
+    }
+    #[inline]
+    /// The number of values in the map.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+    #[inline]
+    /// Check if the map contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.raw.len() == 0
+    }
+    #[inline]
+    /// Clear the map, but preserve the currently reserved capacity.
+    pub fn clear(&mut self) {
+        self.raw.clear()
+    }
+    #[inline]
+    /// Reserve capacity for at least `additional` more values.
+    pub fn reserve(&mut self, additional: usize) {
+        self.raw.reserve(additional, slot_hasher)
+    }
+    #[inline]
+    /// Shrink the capacity of the map as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.raw.shrink_to(0, slot_hasher)
+    }
+    #[inline]
+    /// An iterator visiting all entries in arbitrary order.
+    pub fn iter(&self) -> impl '_ + Iterator<Item = &'_ ErasedEntry<Singleton, I>> {
+        // The iterator borrows the map, preventing use-after-free
+        unsafe { self.raw.iter() }.map(|bucket| unsafe { &bucket.as_ref().1 })
+    }
+    #[inline]
+    fn find<A: 'static>(&self) -> Option<Bucket<Slot<I>>> {
+        let type_id = TypeId::of::<A>();
+        self.raw
+            .find(hash_type_id(type_id), |slot| slot.0 == type_id)
+    }
+    #[inline]
+    /// Check if the map contains a value of type `A`.
+    pub fn contains<A: 'static>(&self) -> bool {
+        self.find::<A>().is_some()
+    }
+    #[inline]
+    /// Returns a reference to the value of type `A`.
+    pub fn get<A: 'static>(&self) -> Option<&A> {
+        match self.find::<A>() {
+            // Found by the type id of `A`, the bucket lives as long as the borrow of the map
+            Some(bucket) => {
+                Some(unsafe { &bucket.as_ref().1.downcast_ref_unchecked::<A>().entry.some })
+            }
+            None => None,
+        }
+    }
+    #[inline]
+    /// Returns a mutable reference to the value of type `A`.
+    pub fn get_mut<A: 'static>(&mut self) -> Option<&mut A> {
+        match self.find::<A>() {
+            // Found by the type id of `A`, the bucket lives as long as the borrow of the map
+            Some(bucket) => {
+                Some(unsafe { &mut bucket.as_mut().1.downcast_mut_unchecked::<A>().entry.some })
+            }
+            None => None,
+        }
+    }
+    /// Inserts a value into the map.
+    ///
+    /// If the map did not have a value of type `A`, [`None`] is returned.
+    ///
+    /// Otherwise, the value is replaced and `Some(old)` where `old` is the old value is returned.
+    pub fn insert<A: 'static>(&mut self, value: A) -> Option<A>
+    where
+        I: CreateEntry<A, Singleton>,
+    {
+        match self.entry::<A>() {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                let _ = entry.insert(value);
+                None
+            }
+        }
+    }
+    /// Removes the value of type `A` from the map, returning it if it was previously in the map.
+    pub fn remove<A: 'static>(&mut self) -> Option<A> {
+        match self.find::<A>() {
+            Some(bucket) => {
+                // Found by the type id of `A`
+                let (_, entry) = unsafe { self.raw.remove(bucket) };
+                Some(unsafe { entry.downcast_unchecked::<A>() }.entry.some)
+            }
+            None => None,
+        }
+    }
+    #[inline]
+    /// Gets the entry for the type `A` in the map for in-place manipulation.
+    pub fn entry<A: 'static>(&mut self) -> Entry<'_, A, I> {
+        match self.find::<A>() {
+            Some(elem) => Entry::Occupied(OccupiedEntry {
+                elem,
+                table: self,
+                _marker: PhantomData,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                table: self,
+                _marker: PhantomData,
+            }),
+        }
+    }
+}
+
+/// An occupied entry in a [`TypeMap`], see [`TypeMap::entry`].
+pub struct OccupiedEntry<'a, A, I: ?Sized> {
+    elem: Bucket<Slot<I>>,
+    // Borrowed mutably for the validity of `elem`
+    table: &'a mut TypeMap<I>,
+    _marker: PhantomData<fn() -> A>,
+}
+
+impl<'a, A: 'static, I: ?Sized> OccupiedEntry<'a, A, I> {
+    #[inline]
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &A {
+        // The bucket is found by the type id of `A` and the map is borrowed
+        unsafe {
+            &self
+                .elem
+                .as_ref()
+                .1
+                .downcast_ref_unchecked::<A>()
+                .entry
+                .some
+        }
+    }
+    #[inline]
+    /// Gets a mutable reference to the value in the entry.
+    pub fn get_mut(&mut self) -> &mut A {
+        // The bucket is found by the type id of `A` and the map is borrowed
+        unsafe {
+            &mut self
+                .elem
+                .as_mut()
+                .1
+                .downcast_mut_unchecked::<A>()
+                .entry
+                .some
+        }
+    }
+    #[inline]
+    /// Converts the entry into a mutable reference to the value in the map.
+    pub fn into_mut(self) -> &'a mut A {
+        // The bucket is found by the type id of `A` and the map is borrowed for 'a
+        unsafe {
+            &mut self
+                .elem
+                .as_mut()
+                .1
+                .downcast_mut_unchecked::<A>()
+                .entry
+                .some
+        }
+    }
+    #[inline]
+    /// Sets the value of the entry, and returns the entry's old value.
+    pub fn insert(&mut self, value: A) -> A {
+        core::mem::replace(self.get_mut(), value)
+    }
+    #[inline]
+    /// Takes the value out of the map.
+    pub fn remove(self) -> A {
+        // The bucket is found by the type id of `A`
+        let (_, entry) = unsafe { self.table.raw.remove(self.elem) };
+        unsafe { entry.downcast_unchecked::<A>() }.entry.some
+    }
+}
+
+/// A vacant entry in a [`TypeMap`], see [`TypeMap::entry`].
+pub struct VacantEntry<'a, A, I: ?Sized> {
+    table: &'a mut TypeMap<I>,
+    _marker: PhantomData<fn() -> A>,
+}
+
+impl<'a, A: 'static, I: ?Sized> VacantEntry<'a, A, I> {
+    #[inline]
+    /// Sets the value of the entry, and returns a mutable reference to it.
+    pub fn insert(self, value: A) -> &'a mut A
+    where
+        I: CreateEntry<A, Singleton>,
+    {
+        let type_id = TypeId::of::<A>();
+        let hash = hash_type_id(type_id);
+        let entry = ErasedEntry::new::<A>(hash, value.into());
+        let slot = self
+            .table
+            .raw
+            .insert_entry(hash, (type_id, entry), slot_hasher);
+        // Just inserted for the argument type `A`
+        unsafe { &mut slot.1.downcast_mut_unchecked::<A>().entry.some }
+    }
+}
+
+/// A view into a single entry in a [`TypeMap`], which may either be vacant or occupied.
+pub enum Entry<'a, A, I: ?Sized> {
+    #[allow(missing_docs)]
+    Occupied(OccupiedEntry<'a, A, I>),
+    #[allow(missing_docs)]
+    Vacant(VacantEntry<'a, A, I>),
+}
+
+impl<'a, A: 'static, I: ?Sized + CreateEntry<A, Singleton>> Entry<'a, A, I> {
+    #[inline]
+    /// Ensures a value is in the entry by inserting `default` if empty.
+    pub fn or_insert(self, default: A) -> &'a mut A {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the result of `default` if empty.
+    pub fn or_insert_with(self, default: impl FnOnce() -> A) -> &'a mut A {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+    #[inline]
+    /// Ensures a value is in the entry by inserting the default value if empty.
+    pub fn or_default(self) -> &'a mut A
+    where
+        A: Default,
+    {
+        self.or_insert_with(A::default)
+    }
+    #[inline]
+    /// Provides in-place mutable access to an occupied entry before any potential inserts.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut A)) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<I: ?Sized> Default for TypeMap<I> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: ?Sized + DynClone> Clone for TypeMap<I> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            raw: self.raw.clone(),
+        }
+    }
+}
+
+impl<I: ?Sized + DebugEntry> Debug for TypeMap<I> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> core::fmt::Result {
+        fmt.debug_map()
+            .entries(self.iter().map(|e| (SomeKey(e), SomeValue(e))))
+            .finish()
+    }
+}
+
+impl<S: BuildHasher, I: ?Sized + HashableAny<S::Hasher>> From<Map<Singleton, S, I>> for TypeMap<I> {
+    fn from(map: Map<Singleton, S, I>) -> Self {
+        let (_, entries) = map.into_raw_parts();
+        let mut raw = RawTable::with_capacity(entries.len());
+        for mut entry in entries {
+            let type_id = entry.type_id();
+            entry.hash = hash_type_id(type_id);
+            let _ = raw.insert(entry.hash, (type_id, entry), slot_hasher);
+        }
+        Self { raw }
+    }
+}